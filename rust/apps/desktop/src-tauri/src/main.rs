@@ -2,17 +2,25 @@
 
 use std::{
   fs,
-  net::{IpAddr, Ipv4Addr, SocketAddr},
-  path::PathBuf,
+  net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+  path::{Path, PathBuf},
+  sync::atomic::{AtomicBool, Ordering},
   time::Duration,
 };
 
 use anyhow::{anyhow, Context, Result};
-use tauri::{Manager, WebviewUrl, WebviewWindowBuilder};
+use tauri::{
+  webview::{PageLoadEvent, PageLoadPayload},
+  Manager, WebviewUrl, WebviewWindow, WebviewWindowBuilder,
+};
 use tracing_subscriber::{fmt, EnvFilter};
 
+#[cfg(feature = "tray")]
+mod tray;
+
 const DEFAULT_CONFIG_JSON: &str = r#"{
   "defaultServerId": "local-qb",
+  "prewarm": true,
   "servers": [
     {
       "id": "local-qb",
@@ -26,6 +34,37 @@ const DEFAULT_CONFIG_JSON: &str = r#"{
 }
 "#;
 
+/// Desktop-only preferences, stored as `desktop.json` next to `standalone.json`.
+const DESKTOP_PREFS_FILE: &str = "desktop.json";
+const DEFAULT_BIND_ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+const APP_TITLE: &str = "TorrentMix";
+const TITLE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Selection cookie set by the gateway; read from the webview so the title
+/// follows the server picked in the UI.
+const COOKIE_SELECTED_SERVER: &str = "tm_server_id";
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct DesktopPrefs {
+  /// Gateway listen address; loopback with an ephemeral port when unset.
+  bind_address: Option<SocketAddr>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StatusView {
+  selected_id: String,
+  servers: Vec<ServerView>,
+}
+
+#[derive(serde::Deserialize)]
+pub(crate) struct ServerView {
+  id: String,
+  name: String,
+  reachable: bool,
+}
+
 fn main() {
   let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
   fmt().with_env_filter(filter).init();
@@ -35,9 +74,17 @@ fn main() {
       let static_dir = resolve_static_dir()?;
       let config_path = resolve_config_path(app)?;
       ensure_config_file(&config_path)?;
+      let prefs = load_desktop_prefs(&config_path)?;
+      let listen = prefs.bind_address.unwrap_or(DEFAULT_BIND_ADDRESS);
+      if !listen.ip().is_loopback() {
+        tracing::warn!(
+          addr = %listen,
+          "gateway is bound to a non-loopback address; other devices on the network can reach \
+           it and the configured backends through it"
+        );
+      }
 
       let addr = tauri::async_runtime::block_on(async move {
-        let listen = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
         let listener = tokio::net::TcpListener::bind(listen)
           .await
           .context("bind gateway listener")?;
@@ -48,13 +95,19 @@ fn main() {
         Ok::<SocketAddr, anyhow::Error>(addr)
       })?;
 
-      let url = format!("http://127.0.0.1:{}/", addr.port());
-      let url = url.parse().context("parse gateway url")?;
+      let url: tauri::Url = format!("http://{}/", webview_addr(addr))
+        .parse()
+        .context("parse gateway url")?;
 
-      WebviewWindowBuilder::new(app, "main", WebviewUrl::External(url))
-        .title("TorrentMix")
-        .build()
-        .context("create main window")?;
+      let mut builder = WebviewWindowBuilder::new(app, "main", WebviewUrl::External(url.clone()))
+        .title(APP_TITLE);
+      if !listen.ip().is_loopback() {
+        builder = builder.on_page_load(exposure_notice(addr));
+      }
+      let window = builder.build().context("create main window")?;
+      #[cfg(feature = "tray")]
+      tray::create(app).context("create tray icon")?;
+      tauri::async_runtime::spawn(watch_status(window, url));
 
       Ok(())
     })
@@ -62,6 +115,118 @@ fn main() {
     .expect("tauri run failed");
 }
 
+/// Polls the gateway status to keep the window title at
+/// "TorrentMix — <selected server> ●/○" (and the tray menu, if built in).
+async fn watch_status(window: WebviewWindow, base: tauri::Url) {
+  let client = reqwest::Client::new();
+  let status_url = match base.join("__standalone__/status") {
+    Ok(v) => v,
+    Err(err) => {
+      tracing::warn!(error = %err, "window title updates disabled");
+      return;
+    }
+  };
+  let mut current = APP_TITLE.to_string();
+  #[cfg(feature = "tray")]
+  let mut tray_shown = String::new();
+  loop {
+    let status = fetch_status(&client, &window, &base, status_url.clone()).await;
+    #[cfg(feature = "tray")]
+    if let Ok(status) = &status {
+      tray::update(window.app_handle(), status, &mut tray_shown);
+    }
+    let title = match status.and_then(|v| title_for(&v)) {
+      Ok(v) => v,
+      Err(err) => {
+        tracing::debug!(error = %format_args!("{err:#}"), "fetch status for window title");
+        APP_TITLE.to_string()
+      }
+    };
+    if title != current {
+      match window.set_title(&title) {
+        Ok(()) => current = title,
+        Err(err) => tracing::debug!(error = %err, "set window title"),
+      }
+    }
+
+    tokio::time::sleep(TITLE_POLL_INTERVAL).await;
+  }
+}
+
+/// Gateway status as seen by the webview, i.e. with its selection cookie.
+async fn fetch_status(
+  client: &reqwest::Client,
+  window: &WebviewWindow,
+  base: &tauri::Url,
+  status_url: tauri::Url,
+) -> Result<StatusView> {
+  let selected = window
+    .cookies_for_url(base.clone())
+    .context("read webview cookies")?
+    .into_iter()
+    .find(|c| c.name() == COOKIE_SELECTED_SERVER)
+    .map(|c| c.value().to_string());
+
+  let mut req = client.get(status_url).timeout(Duration::from_secs(3));
+  if let Some(id) = selected {
+    req = req.header("Cookie", format!("{COOKIE_SELECTED_SERVER}={id}"));
+  }
+  req
+    .send()
+    .await
+    .context("request status")?
+    .error_for_status()
+    .context("status response")?
+    .json()
+    .await
+    .context("parse status")
+}
+
+fn title_for(status: &StatusView) -> Result<String> {
+  let server = status
+    .servers
+    .iter()
+    .find(|s| s.id == status.selected_id)
+    .ok_or_else(|| anyhow!("selected server {:?} not in status", status.selected_id))?;
+  let dot = if server.reachable { "●" } else { "○" };
+  Ok(format!("{APP_TITLE} — {} {dot}", server.name))
+}
+
+/// Page-load hook that shows a one-time alert about the gateway being reachable
+/// from other devices.
+fn exposure_notice(
+  addr: SocketAddr,
+) -> impl Fn(WebviewWindow, PageLoadPayload<'_>) + Send + Sync + 'static {
+  let shown = AtomicBool::new(false);
+  move |window, payload| {
+    if payload.event() != PageLoadEvent::Finished || shown.swap(true, Ordering::Relaxed) {
+      return;
+    }
+    let message = format!(
+      "网关正在监听 {addr}，局域网内的其他设备可以访问它以及其代理的后端。\
+       如非必要，请在 desktop.json 中将 bindAddress 改回 127.0.0.1。"
+    );
+    let script = format!("alert({})", serde_json::Value::String(message));
+    if let Err(err) = window.eval(script) {
+      tracing::debug!(error = %err, "show exposure notice");
+    }
+  }
+}
+
+/// Address the webview loads from: the bound address itself, or loopback when
+/// bound to all interfaces.
+fn webview_addr(addr: SocketAddr) -> SocketAddr {
+  match addr.ip() {
+    IpAddr::V4(ip) if ip.is_unspecified() => {
+      SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), addr.port())
+    }
+    IpAddr::V6(ip) if ip.is_unspecified() => {
+      SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), addr.port())
+    }
+    _ => addr,
+  }
+}
+
 fn resolve_static_dir() -> Result<PathBuf> {
   if let Ok(v) = std::env::var("STATIC_DIR") {
     let v = v.trim();
@@ -104,6 +269,18 @@ fn resolve_config_path(app: &tauri::App) -> Result<PathBuf> {
   Ok(dir.join("standalone.json"))
 }
 
+fn load_desktop_prefs(config_path: &Path) -> Result<DesktopPrefs> {
+  let path = config_path.with_file_name(DESKTOP_PREFS_FILE);
+  let raw = match fs::read(&path) {
+    Ok(v) => v,
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+      return Ok(DesktopPrefs::default());
+    }
+    Err(err) => return Err(err).with_context(|| format!("read {}", path.display())),
+  };
+  serde_json::from_slice(&raw).with_context(|| format!("parse {}", path.display()))
+}
+
 fn ensure_config_file(path: &PathBuf) -> Result<()> {
   if path.exists() {
     return Ok(());
@@ -0,0 +1,106 @@
+//! Tray icon with a server switcher, built with the `tray` feature.
+
+use anyhow::{Context, Result};
+use tauri::{
+  menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem},
+  tray::TrayIconBuilder,
+  AppHandle, Manager,
+};
+
+use crate::{StatusView, APP_TITLE};
+
+const TRAY_ID: &str = "main";
+const QUIT_ID: &str = "quit";
+const SERVER_ID_PREFIX: &str = "server:";
+
+pub(crate) fn create(app: &tauri::App) -> Result<()> {
+  let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+    .tooltip(APP_TITLE)
+    .menu(&build_menu(app.handle(), None)?)
+    .show_menu_on_left_click(true)
+    .on_menu_event(handle_menu_event);
+  if let Some(icon) = app.default_window_icon() {
+    builder = builder.icon(icon.clone());
+  }
+  builder.build(app).context("build tray icon")?;
+  Ok(())
+}
+
+/// Rebuilds the menu when the server list or the selection changed since
+/// `shown` was recorded.
+pub(crate) fn update(app: &AppHandle, status: &StatusView, shown: &mut String) {
+  let key = status
+    .servers
+    .iter()
+    .map(|s| format!("{}={}", s.id, s.name))
+    .chain([status.selected_id.clone()])
+    .collect::<Vec<_>>()
+    .join("\n");
+  if key == *shown {
+    return;
+  }
+  let Some(tray) = app.tray_by_id(TRAY_ID) else {
+    return;
+  };
+  let menu = match build_menu(app, Some(status)) {
+    Ok(v) => v,
+    Err(err) => {
+      tracing::debug!(error = %format_args!("{err:#}"), "build tray menu");
+      return;
+    }
+  };
+  match tray.set_menu(Some(menu)) {
+    Ok(()) => *shown = key,
+    Err(err) => tracing::debug!(error = %err, "set tray menu"),
+  }
+}
+
+fn build_menu(app: &AppHandle, status: Option<&StatusView>) -> Result<Menu<tauri::Wry>> {
+  let menu = Menu::new(app).context("create menu")?;
+  if let Some(status) = status {
+    for server in &status.servers {
+      let item = CheckMenuItem::with_id(
+        app,
+        format!("{SERVER_ID_PREFIX}{}", server.id),
+        &server.name,
+        true,
+        server.id == status.selected_id,
+        None::<&str>,
+      )
+      .context("create server item")?;
+      menu.append(&item).context("append server item")?;
+    }
+    menu
+      .append(&PredefinedMenuItem::separator(app).context("create separator")?)
+      .context("append separator")?;
+  }
+  let quit = MenuItem::with_id(app, QUIT_ID, "退出", true, None::<&str>)
+    .context("create quit item")?;
+  menu.append(&quit).context("append quit item")?;
+  Ok(menu)
+}
+
+/// Selecting a server goes through the webview so that it receives the
+/// selection cookie, then reloads it.
+fn handle_menu_event(app: &AppHandle, event: MenuEvent) {
+  let id = event.id().as_ref();
+  if id == QUIT_ID {
+    app.exit(0);
+    return;
+  }
+  let Some(server_id) = id.strip_prefix(SERVER_ID_PREFIX) else {
+    return;
+  };
+  let Some(window) = app.get_webview_window("main") else {
+    return;
+  };
+  let body = serde_json::json!({ "id": server_id }).to_string();
+  let script = format!(
+    "fetch('/__standalone__/select', {{ method: 'POST', headers: {{ 'Content-Type': \
+     'application/json' }}, body: {} }}).then(() => location.reload())",
+    serde_json::Value::String(body)
+  );
+  if let Err(err) = window.eval(script) {
+    tracing::warn!(error = %err, "switch server from tray");
+  }
+}
@@ -1,13 +1,42 @@
+use std::path::PathBuf;
+
+use clap::Parser;
 use tracing_subscriber::{fmt, EnvFilter};
 
+/// Same-origin gateway serving the TorrentMix WebUI and proxying its backends.
+///
+/// Options override their environment variables, which override the defaults.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+  /// Config file path [env: STANDALONE_CONFIG] [default: /config/standalone.json]
+  #[arg(long)]
+  config: Option<PathBuf>,
+  /// Listen address, e.g. `:8080` or `127.0.0.1:8080` [env: LISTEN_ADDR] [default: :8080]
+  #[arg(long)]
+  listen: Option<String>,
+  /// Directory with the built WebUI [env: STATIC_DIR] [default: ./dist]
+  #[arg(long)]
+  static_dir: Option<PathBuf>,
+}
+
 #[tokio::main]
 async fn main() {
+  let args = Args::parse();
+
   let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
   fmt().with_env_filter(filter).init();
 
-  if let Err(err) = gateway::serve_from_env().await {
-    tracing::error!(error = %err, "standalone-service failed");
+  let listen = args.listen.unwrap_or_else(|| gateway::env_or_default("LISTEN_ADDR", ":8080"));
+  let static_dir = args
+    .static_dir
+    .unwrap_or_else(|| gateway::env_or_default("STATIC_DIR", "./dist").into());
+  let config = args.config.unwrap_or_else(|| {
+    gateway::env_or_default("STANDALONE_CONFIG", "/config/standalone.json").into()
+  });
+
+  if let Err(err) = gateway::serve(&listen, static_dir, config).await {
+    tracing::error!(error = %format_args!("{err:#}"), "standalone-service failed");
     std::process::exit(1);
   }
 }
-
@@ -0,0 +1,33 @@
+use std::process::Command;
+
+fn main() {
+  println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+
+  if let Some(dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+    println!("cargo:rerun-if-changed={dir}/HEAD");
+    println!("cargo:rerun-if-changed={dir}/refs");
+  }
+
+  let commit = std::env::var("GIT_COMMIT")
+    .ok()
+    .map(|v| v.trim().to_string())
+    .filter(|v| !v.is_empty())
+    .or_else(|| git(&["rev-parse", "--short=12", "HEAD"]));
+
+  if let Some(commit) = commit {
+    println!("cargo:rustc-env=GATEWAY_GIT_COMMIT={commit}");
+  }
+}
+
+fn git(args: &[&str]) -> Option<String> {
+  let out = Command::new("git").args(args).output().ok()?;
+  if !out.status.success() {
+    return None;
+  }
+  let v = String::from_utf8(out.stdout).ok()?;
+  let v = v.trim();
+  if v.is_empty() {
+    return None;
+  }
+  Some(v.to_string())
+}
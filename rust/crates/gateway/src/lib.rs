@@ -1,46 +1,198 @@
 use std::{
-  collections::HashMap,
+  collections::{BTreeMap, HashMap, VecDeque},
+  io::{Read, Write},
   net::{IpAddr, Ipv4Addr, SocketAddr},
   path::{Path, PathBuf},
-  sync::Arc,
-  time::Duration,
+  sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+  },
+  time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{anyhow, Context, Result};
 use axum::{
   body::Body,
-  extract::State,
+  extract::{ConnectInfo, Path as AxumPath, Query, State},
   http::{
     header::{self, HeaderName},
     HeaderMap, HeaderValue, Method, Request, StatusCode, Uri,
   },
-  response::{IntoResponse, Response},
-  routing::{any, get, post},
+  middleware::{self, Next},
+  response::{
+    sse::{Event, KeepAlive, Sse},
+    IntoResponse, Response,
+  },
+  routing::{any, get, patch, post},
   Json, Router,
 };
 use axum_extra::extract::cookie::CookieJar;
-use bytes::Bytes;
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use bytes::{Bytes, BytesMut};
 use futures_util::{StreamExt, TryStreamExt};
+use ipnet::IpNet;
 use reqwest::redirect::Policy;
 use tokio::{
   net::TcpStream,
-  sync::{Mutex, RwLock},
+  sync::{oneshot, watch, Mutex, RwLock, Semaphore},
   time::{timeout_at, Instant},
 };
-use tower_http::services::{ServeDir, ServeFile};
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+use tower_http::{
+  catch_panic::CatchPanicLayer,
+  services::{ServeDir, ServeFile},
+};
 use url::Url;
 
 const COOKIE_SELECTED_SERVER: &str = "tm_server_id";
+const HEADER_SERVER_OVERRIDE: &str = "x-torrentmix-server";
+const HEADER_CONFIRM_DESTRUCTIVE: &str = "x-confirm-destructive";
+const HEADER_UPSTREAM_TIMEOUT: &str = "x-upstream-timeout";
+const HEADER_MAX_BODY_BYTES: &str = "x-max-body-bytes";
 const MAX_BODY_BYTES: usize = 64 << 20;
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+const MAX_CONFIG_BODY_BYTES: usize = 64 * 1024;
+const MAX_SELECT_BODY_BYTES: usize = 1024;
+const MAX_URI_BYTES: usize = 8 * 1024;
+const SCHEMA_VERSION: u32 = 1;
+const TRANSMISSION_SESSION_HEADER: &str = "x-transmission-session-id";
+const MAX_PROBE_RESPONSE_BYTES: usize = 1 << 20;
+const PROXY_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(600);
+const QBIT_LOGIN_TIMEOUT: Duration = Duration::from_secs(12);
+const LOGIN_RETRY_BACKOFF: Duration = Duration::from_millis(250);
+const LOGIN_RETRY_BACKOFF_MAX: Duration = Duration::from_secs(2);
+const STATUS_PROBE_TIMEOUT: Duration = Duration::from_millis(1200);
+const STATUS_CONCURRENCY: usize = 16;
+/// Pause between a failed probe connect and the next `statusConnectRetries`
+/// attempt.
+const STATUS_RETRY_DELAY: Duration = Duration::from_millis(100);
+const HEALTH_INTERVAL_SECS: u64 = 30;
+const FAVICON_TTL: Duration = Duration::from_secs(3600);
+/// How long a server without a reachable favicon gets the placeholder before
+/// the backend is asked again.
+const FAVICON_MISS_TTL: Duration = Duration::from_secs(60);
+/// Upper bound for `retryAfterJitterSecs`.
+const MAX_RETRY_AFTER_JITTER_SECS: u64 = 3600;
+/// How long an `auto` server whose type could not be detected is left alone
+/// before requests probe it again.
+const DETECT_RETRY_AFTER: Duration = Duration::from_secs(30);
+const MAX_FAVICON_BYTES: usize = 256 * 1024;
+const MAX_CACHED_RESPONSE_BYTES: usize = 1 << 20;
+/// Largest `401`/`403` body inspected for qBittorrent's cross-site rejection.
+const MAX_CSRF_BODY_BYTES: u64 = 64;
+const DEFAULT_INSPECT_BODY_MAX_BYTES: usize = 4 << 20;
+const FORWARD_AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_FORWARD_AUTH_HEADERS: &[&str] = &[
+  "remote-user",
+  "remote-groups",
+  "remote-email",
+  "remote-name",
+  "x-auth-request-user",
+  "x-auth-request-email",
+  "x-auth-request-groups",
+];
+const MAX_LOGGED_BODY_BYTES: usize = 4096;
+const DEFAULT_LOG_REDACT_KEYS: &[&str] =
+  &["password", "passwd", "secret", "token", "apikey", "api_key"];
+const MAX_COMMAND_RESPONSE_BYTES: usize = 128 << 20;
+const COMMAND_POOL_SIZE: usize = 4;
+const PLACEHOLDER_FAVICON_SVG: &str = "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 16 16\"><circle cx=\"8\" cy=\"8\" r=\"7\" fill=\"#9ca3af\"/></svg>";
+const FALLBACK_INDEX_HTML: &str = "<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>TorrentMix</title></head>\n<body><h1>TorrentMix gateway is running</h1>\n<p>The web UI was not found. Check that <code>STATIC_DIR</code> points at the frontend build (it must contain <code>index.html</code>).</p></body></html>\n";
+
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize, schemars::JsonSchema,
+)]
 #[serde(rename_all = "lowercase")]
 enum BackendType {
   Qbit,
   Trans,
+  /// An external process speaking the gateway's JSON envelope on stdin/stdout.
+  Command,
+  /// qBittorrent or Transmission, detected by probing the server.
+  Auto,
+}
+
+impl BackendType {
+  /// The serialized name, e.g. for metric labels.
+  fn label(self) -> &'static str {
+    match self {
+      BackendType::Qbit => "qbit",
+      BackendType::Trans => "trans",
+      BackendType::Command => "command",
+      BackendType::Auto => "auto",
+    }
+  }
+}
+
+/// Handling of a backend route (`/api/*`, `/transmission/*`) that does not
+/// match the type of the server it resolved to.
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+enum RouteMismatch {
+  /// Forward anyway and let the backend answer.
+  Proxy,
+  /// Answer `404` from the gateway, naming the mismatch.
+  Reject,
+}
+
+/// How the gateway authenticates against a qBittorrent server.
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+enum QbitAuth {
+  /// Log in with `username` / `password` and forward the session cookie.
+  Password,
+  /// No login; qBittorrent bypasses auth for the gateway (localhost or
+  /// IP whitelist).
+  None,
+}
+
+/// Pattern syntax for path allow/deny lists.
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+enum PathMatch {
+  /// `*` matches within one path segment, `**` across segments.
+  Glob,
+  /// Unanchored regular expressions; use `^`/`$` to anchor.
+  Regex,
+}
+
+/// A `GET` path pattern (interpreted per `pathMatch`) whose `200` responses
+/// are cached for `ttlSecs`.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct ResponseCacheRule {
+  path: String,
+  ttl_secs: u64,
+}
+
+/// Literal text replacement applied to textual response bodies.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+struct RewriteRule {
+  from: String,
+  to: String,
+}
+
+/// HTTP/2 mode for upstream connections.
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+enum Http2Mode {
+  /// Negotiated via TLS ALPN, falling back to HTTP/1.1 (`https` only).
+  Alpn,
+  /// HTTP/2 without negotiation; also works over plain `http` (h2c).
+  PriorKnowledge,
 }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct ServerConfig {
   #[serde(default)]
@@ -49,247 +201,2454 @@ struct ServerConfig {
   name: String,
   #[serde(rename = "type")]
   kind: BackendType,
+  /// Not used by `command` backends.
   #[serde(default)]
   base_url: String,
+  /// Program and arguments of a `command` backend.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  command: Vec<String>,
   #[serde(default)]
   username: String,
   #[serde(default)]
   password: String,
+  /// qBittorrent only; defaults to `password`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  auth: Option<QbitAuth>,
+  #[serde(default = "default_true", skip_serializing_if = "is_true")]
+  enabled: bool,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  client_cert_path: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  client_key_path: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  ca_cert_path: Option<String>,
+  /// Enables HTTP/2 to this server; HTTP/1.1 is used when unset.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  http2: Option<Http2Mode>,
+  /// Overrides the top-level `upstreamProxy`; `"none"` connects directly.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  upstream_proxy: Option<String>,
+  /// Only request paths matching one of these are proxied (all when empty).
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  allowed_paths: Vec<String>,
+  /// Request paths matching any of these are rejected with `403`.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  denied_paths: Vec<String>,
+  /// How `allowedPaths` / `deniedPaths` are interpreted; defaults to `glob`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  path_match: Option<PathMatch>,
+  /// Read-through cache for slow-changing `GET` endpoints.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  response_cache: Vec<ResponseCacheRule>,
+  /// Request paths (interpreted per `pathMatch`) that are rejected with
+  /// `428` unless the request carries `X-Confirm-Destructive: yes`.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  destructive_paths: Vec<String>,
+  /// Transmission RPC methods (e.g. `torrent-remove`) gated like
+  /// `destructivePaths`.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  destructive_rpc_methods: Vec<String>,
+  /// Replacements applied in order to small, uncompressed text and JSON
+  /// responses, e.g. to fix absolute URLs of a backend under a subpath.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  rewrite: Vec<RewriteRule>,
+  /// Proxies every path the gateway does not handle itself (including `/`)
+  /// to this server while it is selected, exposing its native WebUI.
+  #[serde(default, skip_serializing_if = "is_false")]
+  passthrough_ui: bool,
+  /// Sends the client's address as `X-Forwarded-For` / `X-Real-IP`.
+  /// Client-supplied forwarding headers are dropped either way.
+  #[serde(default, skip_serializing_if = "is_false")]
+  forward_client_ip: bool,
+  /// Transmission only: rejects RPC bodies that are not a JSON object with a
+  /// string `method` with `400` instead of forwarding them.
+  #[serde(default, skip_serializing_if = "is_false")]
+  validate_rpc: bool,
+  /// Overrides the top-level `userAgent` for this server.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  user_agent: Option<String>,
+  /// Client request headers dropped before forwarding to this server.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  strip_request_headers: Vec<String>,
+  /// Query parameters added to every proxied request, e.g. an `apikey` for
+  /// backends that authenticate by query string. Keys the client already
+  /// sent are left alone unless `forceQueryParams` is set.
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  query_params: BTreeMap<String, String>,
+  /// `queryParams` replace client-supplied parameters of the same name.
+  #[serde(default, skip_serializing_if = "is_false")]
+  force_query_params: bool,
+  /// `Host` (`host[:port]`) sent upstream instead of the one in `baseUrl`,
+  /// for backends behind a virtual-host proxy.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  upstream_host: Option<String>,
+  /// Requests forwarded to this server at once; excess requests wait and
+  /// are admitted round-robin across client IPs.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  max_concurrent_requests: Option<usize>,
+  /// Request body cap for this server, for buffered bodies and streamed
+  /// uploads alike; overrides `maxUploadBytes` / `maxStreamUploadBytes`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  max_upload_bytes: Option<u64>,
+  /// Hex color (`#rgb` or `#rrggbb`) the UI uses to tag this server.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  color: Option<String>,
+  /// Free-form icon name for the UI.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  icon: Option<String>,
+  /// Added at runtime by mDNS discovery; never written to the config file.
+  #[serde(skip)]
+  discovered: bool,
+  /// `kind` was detected for a server declared as `auto`.
+  #[serde(skip)]
+  auto_detected: bool,
+}
+
+impl ServerConfig {
+  /// The type as written in the config file.
+  fn declared_kind(&self) -> BackendType {
+    if self.auto_detected {
+      BackendType::Auto
+    } else {
+      self.kind
+    }
+  }
+
+  /// Whether requests to this server need a qBittorrent session cookie.
+  fn needs_qbit_login(&self) -> bool {
+    self.kind == BackendType::Qbit && self.auth != Some(QbitAuth::None)
+  }
+}
+
+fn default_true() -> bool {
+  true
+}
+
+fn is_true(v: &bool) -> bool {
+  *v
+}
+
+fn is_false(v: &bool) -> bool {
+  !*v
 }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+/// Contents of `standalone.json`.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct ConfigFile {
   #[serde(default)]
   default_server_id: String,
+  /// Default qBittorrent server; falls back to the first enabled one.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  default_qbit_id: Option<String>,
+  /// Default Transmission server; falls back to the first enabled one.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  default_trans_id: Option<String>,
   servers: Vec<ServerConfig>,
+  #[serde(flatten)]
+  settings: GatewaySettings,
 }
 
-#[derive(Debug, Clone)]
-struct ServerEntry {
-  cfg: ServerConfig,
-  base: Url,
-  origin: String,
+/// Gateway-wide options stored at the top level of the config file. They are
+/// not editable through the config API and are carried over on every save.
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct GatewaySettings {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  max_upload_bytes: Option<usize>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  max_config_body_bytes: Option<usize>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  max_select_body_bytes: Option<usize>,
+  /// Longest proxied path plus query accepted; longer ones get `414`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  max_uri_bytes: Option<usize>,
+  /// Cap for streamed `multipart/form-data` uploads; unlimited when unset.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  max_stream_upload_bytes: Option<u64>,
+  /// Login attempts per qBittorrent session refresh; only network errors and
+  /// 5xx responses are retried.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  qbit_login_attempts: Option<u32>,
+  /// Public path prefix the gateway is mounted under by a fronting proxy;
+  /// `index.html` gets a matching `<base href>`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  base_path: Option<String>,
+  /// Sets `TCP_NODELAY` on accepted connections. Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  tcp_nodelay: Option<bool>,
+  /// Enables TCP keep-alive with this idle time. Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  tcp_keepalive_secs: Option<u64>,
+  /// Maintenance state restored at startup; written when toggled with
+  /// `persist: true`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  maintenance: Option<Maintenance>,
+  /// Routes `/api/*` to qBittorrent and `/transmission/*` to Transmission
+  /// servers, using the selection only when its type matches the route.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  strict_route_typing: Option<bool>,
+  /// What to do when `/api/*` or `/transmission/*` reaches a server of the
+  /// other backend type (default `proxy`).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  route_mismatch: Option<RouteMismatch>,
+  /// Lets identical concurrent `GET`s (same server, path, query and client
+  /// headers that affect the answer) share one upstream call.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  coalesce_gets: Option<bool>,
+  /// Connects to (and logs in to) every enabled server at startup, so the
+  /// first request does not pay for it. Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  prewarm: Option<bool>,
+  /// Background health probe interval; `0` disables the monitor and
+  /// `/__standalone__/status` probes on every request. Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  health_interval_secs: Option<u64>,
+  /// HTTP(S) or SOCKS5 proxy for all outbound upstream connections.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  upstream_proxy: Option<String>,
+  /// Upstream response headers to drop before replying to the client.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  strip_response_headers: Vec<String>,
+  /// Headers set on every proxied response, replacing upstream values.
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  add_response_headers: BTreeMap<String, String>,
+  /// HTML file served in place of a missing `index.html`; a built-in notice
+  /// is used when unset or unreadable.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  fallback_page: Option<String>,
+  /// Deadline for reachability probes in `/status` and the health monitor.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  status_probe_timeout_ms: Option<u64>,
+  /// Extra connect attempts after a failed probe, within the same deadline.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  status_connect_retries: Option<u32>,
+  /// Extra path prefixes (e.g. `/plugin`) proxied to the selected server
+  /// like `/api`. Read at startup only.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  proxy_prefixes: Vec<String>,
+  /// Cap on requests handled at once; excess requests get `503`. Read at
+  /// startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  max_connections: Option<usize>,
+  /// `Retry-After` of requests shed by `maxConnections`, in seconds
+  /// (default 1). Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  retry_after_secs: Option<u64>,
+  /// Random extra seconds, up to this many, added to each `Retry-After` so
+  /// shed clients do not all retry at once. Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  retry_after_jitter_secs: Option<u64>,
+  /// TCP listen backlog (OS default when unset). Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  listen_backlog: Option<u32>,
+  /// Consecutive failed probes before a server is reported unreachable.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  reachable_failure_threshold: Option<u32>,
+  /// Servers probed at once by `/status` and the health monitor.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  status_concurrency: Option<usize>,
+  /// Upstream requests slower than this are logged as warnings.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  slow_request_threshold_ms: Option<u64>,
+  /// Upper bound for per-request `X-Upstream-Timeout` values.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  max_upstream_timeout_secs: Option<u64>,
+  /// Logs out of qBittorrent sessions unused for this long; `0` or unset
+  /// keeps them until the backend expires them. Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  session_idle_secs: Option<u64>,
+  /// `Domain` attribute of the server selection cookie, to share it across
+  /// hostnames of one deployment.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  cookie_domain: Option<String>,
+  /// `User-Agent` sent upstream in place of the client's.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  user_agent: Option<String>,
+  /// Adds `_http._tcp` services found on the LAN as servers. Requires the
+  /// `mdns` build feature. Read at startup only.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  mdns_discovery: Vec<MdnsRule>,
+  /// API-only mode: no static frontend, `/` describes the gateway endpoints.
+  /// Defaults to the `HEADLESS` environment variable. Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  headless: Option<bool>,
+  /// Serves `.br`/`.gz` siblings of static files to clients that accept
+  /// them, falling back to the plain file.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  precompressed_static: Option<bool>,
+  /// PEM certificate chain; with `tlsKeyPath`, the gateway serves HTTPS.
+  /// Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  tls_cert_path: Option<PathBuf>,
+  /// PEM private key (PKCS#8, PKCS#1 or SEC1) for `tlsCertPath`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  tls_key_path: Option<PathBuf>,
+  /// Largest upstream response body buffered for inspection (rewrite rules,
+  /// response cache, version queries); larger bodies are streamed untouched.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  inspect_body_max_bytes: Option<usize>,
+  /// Logs every proxied request (method, path, query, form body, status,
+  /// duration) at info level, with `logRedactKeys` fields masked.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  access_log: Option<bool>,
+  /// Field names masked in the access log when they contain one of these
+  /// (case-insensitive); replaces the built-in list of common secret names.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  log_redact_keys: Option<Vec<String>>,
+  /// CIDR ranges (or single addresses) allowed to reach the gateway; all
+  /// clients when empty. Read at startup only.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  allow_ips: Vec<String>,
+  /// CIDR ranges (or single addresses) refused with `403`, checked before
+  /// `allowIps`. Read at startup only.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  deny_ips: Vec<String>,
+  /// Reverse proxies whose `X-Forwarded-For` is trusted to name the client for
+  /// `allowIps` / `denyIps`, and whose `X-Forwarded-Host` / `-Proto` are used
+  /// to retry qBittorrent cross-site rejections. Read at startup only.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  trusted_proxies: Vec<String>,
+  /// External auth check (Authelia, oauth2-proxy, ...) run before protected
+  /// routes. Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  forward_auth: Option<ForwardAuth>,
+  /// Lowest TLS version accepted from upstreams: `"1.0"` to `"1.3"`;
+  /// reqwest's default when unset. Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  min_tls_version: Option<String>,
+  /// `false` turns the config API read-only: every route that writes the
+  /// config answers `403`, independent of file permissions (default `true`).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  config_editable: Option<bool>,
+  /// Without a selection cookie, use a reachable server while the health
+  /// monitor reports the default server down (default `false`).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  auto_failover_default: Option<bool>,
+  /// Plain-HTTP listen address (e.g. `":80"`) that answers every request with
+  /// a `301` to HTTPS. Requires `tlsCertPath`. Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  http_redirect_addr: Option<String>,
+  /// External HTTPS origin for `httpRedirectAddr` redirects, e.g.
+  /// `"https://torrents.example.com"`; defaults to the request's host on the
+  /// gateway's HTTPS port. Read at startup only.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  https_origin: Option<String>,
 }
 
-#[derive(Debug)]
-struct Catalog {
-  default_id: String,
-  servers: HashMap<String, ServerEntry>,
-  order: Vec<String>,
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct ForwardAuth {
+  /// Called with the client's cookies and `Authorization`; a `2xx` answer
+  /// lets the request through, anything else is relayed to the client.
+  url: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  timeout_ms: Option<u64>,
+  /// Gate `/__standalone__/*` and the web UI (default `true`);
+  /// `/__standalone__/version` and `/__standalone__/health` stay open.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  management: Option<bool>,
+  /// Gate proxied backend routes (default `true`).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  proxy: Option<bool>,
+  /// Identity headers copied from a successful check onto the request;
+  /// client-sent copies are always dropped.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  copy_headers: Option<Vec<String>>,
 }
 
-impl Catalog {
-  fn load(path: &Path) -> Result<Self> {
-    let raw = std::fs::read(path).with_context(|| format!("read config: {}", path.display()))?;
-    let mut cfg: ConfigFile =
-      serde_json::from_slice(&raw).context("parse config")?;
-
-    if cfg.servers.is_empty() {
-      return Err(anyhow!("config.servers is empty"));
-    }
-
-    cfg.default_server_id = cfg.default_server_id.trim().to_string();
+/// Discovered service instances whose name matches `namePattern` become
+/// servers of `type`, logged in with the given credentials.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct MdnsRule {
+  /// Case-insensitive glob, e.g. `qBittorrent*`.
+  name_pattern: String,
+  #[serde(rename = "type")]
+  kind: BackendType,
+  #[serde(default, skip_serializing_if = "String::is_empty")]
+  username: String,
+  #[serde(default, skip_serializing_if = "String::is_empty")]
+  password: String,
+}
 
-    let mut servers = HashMap::with_capacity(cfg.servers.len());
-    let mut order = Vec::with_capacity(cfg.servers.len());
+impl MdnsRule {
+  fn matcher(&self) -> Result<globset::GlobMatcher> {
+    let glob = globset::GlobBuilder::new(self.name_pattern.trim())
+      .case_insensitive(true)
+      .build()
+      .with_context(|| format!("mdnsDiscovery: invalid namePattern {:?}", self.name_pattern))?;
+    Ok(glob.compile_matcher())
+  }
+}
 
-    for mut s in cfg.servers.drain(..) {
-      s.id = s.id.trim().to_string();
-      s.name = s.name.trim().to_string();
-      s.base_url = s.base_url.trim().to_string();
-      s.username = s.username.trim().to_string();
-      s.password = s.password.trim().to_string();
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct Maintenance {
+  enabled: bool,
+  #[serde(default, skip_serializing_if = "String::is_empty")]
+  message: String,
+}
 
-      if s.id.is_empty() {
-        return Err(anyhow!("server.id is required"));
+impl GatewaySettings {
+  fn validate(&self) -> Result<()> {
+    for (key, value) in [
+      ("maxUploadBytes", self.max_upload_bytes),
+      ("maxConfigBodyBytes", self.max_config_body_bytes),
+      ("maxSelectBodyBytes", self.max_select_body_bytes),
+    ] {
+      if value == Some(0) {
+        return Err(anyhow!("{key} must be greater than 0"));
       }
-      if s.name.is_empty() {
-        s.name = s.id.clone();
+    }
+    if self.max_stream_upload_bytes == Some(0) {
+      return Err(anyhow!("maxStreamUploadBytes must be greater than 0"));
+    }
+    if self.tcp_keepalive_secs == Some(0) {
+      return Err(anyhow!("tcpKeepaliveSecs must be greater than 0"));
+    }
+    if let Some(v) = &self.upstream_proxy {
+      parse_proxy_url(v).context("upstreamProxy")?;
+    }
+    if let Some(v) = &self.user_agent {
+      HeaderValue::from_str(v.trim()).context("userAgent: invalid header value")?;
+    }
+    for rule in &self.mdns_discovery {
+      rule.matcher()?;
+      if rule.kind == BackendType::Command {
+        return Err(anyhow!("mdnsDiscovery: type \"command\" cannot be discovered"));
       }
-      if s.base_url.is_empty() {
-        return Err(anyhow!("server {:?}: baseUrl is required", s.id));
+    }
+    if self.max_connections == Some(0) {
+      return Err(anyhow!("maxConnections must be greater than 0"));
+    }
+    if self.retry_after_jitter_secs.is_some_and(|v| v > MAX_RETRY_AFTER_JITTER_SECS) {
+      return Err(anyhow!("retryAfterJitterSecs must be at most {MAX_RETRY_AFTER_JITTER_SECS}"));
+    }
+    if self.listen_backlog == Some(0) {
+      return Err(anyhow!("listenBacklog must be greater than 0"));
+    }
+    if let Some(v) = &self.cookie_domain {
+      if !is_plausible_domain(v.trim()) {
+        return Err(anyhow!("cookieDomain {:?} is not a valid domain", v));
       }
-      if servers.contains_key(&s.id) {
-        return Err(anyhow!("duplicate server id: {:?}", s.id));
+    }
+    if self.tls_cert_path.is_some() != self.tls_key_path.is_some() {
+      return Err(anyhow!("tlsCertPath and tlsKeyPath must be set together"));
+    }
+    if let Some(addr) = &self.http_redirect_addr {
+      normalize_listen_addr(addr).context("httpRedirectAddr")?;
+      if self.tls_cert_path.is_none() {
+        return Err(anyhow!("httpRedirectAddr requires tlsCertPath and tlsKeyPath"));
       }
-
-      let base = Url::parse(&s.base_url)
-        .with_context(|| format!("server {:?}: invalid baseUrl {:?}", s.id, s.base_url))?;
-      if base.scheme().is_empty() || base.host_str().is_none() {
-        return Err(anyhow!("server {:?}: invalid baseUrl {:?}", s.id, s.base_url));
+    }
+    if let Some(v) = &self.https_origin {
+      self.https_origin_url().with_context(|| format!("httpsOrigin {v:?}"))?;
+    }
+    IpFilter::from_settings(self)?;
+    if let Some(v) = &self.min_tls_version {
+      parse_tls_version(v)?;
+    }
+    ForwardAuthGate::from_settings(self)?;
+    if self.max_uri_bytes == Some(0) {
+      return Err(anyhow!("maxUriBytes must be greater than 0"));
+    }
+    if self.inspect_body_max_bytes == Some(0) {
+      return Err(anyhow!("inspectBodyMaxBytes must be greater than 0"));
+    }
+    if self.max_upstream_timeout_secs == Some(0) {
+      return Err(anyhow!("maxUpstreamTimeoutSecs must be greater than 0"));
+    }
+    if self.slow_request_threshold_ms == Some(0) {
+      return Err(anyhow!("slowRequestThresholdMs must be greater than 0"));
+    }
+    if self.status_concurrency == Some(0) {
+      return Err(anyhow!("statusConcurrency must be greater than 0"));
+    }
+    if self.reachable_failure_threshold == Some(0) {
+      return Err(anyhow!("reachableFailureThreshold must be greater than 0"));
+    }
+    if self.status_probe_timeout_ms == Some(0) {
+      return Err(anyhow!("statusProbeTimeoutMs must be greater than 0"));
+    }
+    if self.qbit_login_attempts == Some(0) {
+      return Err(anyhow!("qbitLoginAttempts must be greater than 0"));
+    }
+    if let Some(v) = &self.base_path {
+      let v = v.trim();
+      if !v.is_empty() && !v.starts_with('/') {
+        return Err(anyhow!("basePath {:?} must start with '/'", v));
+      }
+      if v.contains(['"', '\'', '<', '>']) {
+        return Err(anyhow!("basePath {:?} contains invalid characters", v));
       }
-
-      let host = base.host_str().unwrap();
-      let host_for_origin = format_host_only(host);
-      let origin = if let Some(port) = base.port() {
-        format!("{}://{}:{}", base.scheme(), host_for_origin, port)
-      } else {
-        format!("{}://{}", base.scheme(), host_for_origin)
-      };
-      let entry = ServerEntry { cfg: s, base, origin };
-      order.push(entry.cfg.id.clone());
-      servers.insert(entry.cfg.id.clone(), entry);
     }
+    let mut prefixes = Vec::with_capacity(self.proxy_prefixes.len());
+    for raw in &self.proxy_prefixes {
+      let v = raw.trim().trim_end_matches('/');
+      if !raw.trim().starts_with('/') {
+        return Err(anyhow!("proxyPrefixes: {:?} must start with '/'", raw));
+      }
+      if v.is_empty() || v.contains(['*', ':', '{', '}', '?', '#']) {
+        return Err(anyhow!("proxyPrefixes: {:?} is not a valid path prefix", raw));
+      }
+      let first = v.split('/').nth(1).unwrap_or("");
+      if matches!(first, "__standalone__" | "api" | "transmission") {
+        return Err(anyhow!("proxyPrefixes: {:?} overlaps a built-in route", raw));
+      }
+      if prefixes.contains(&v) {
+        return Err(anyhow!("proxyPrefixes: duplicate prefix {:?}", raw));
+      }
+      prefixes.push(v);
+    }
+    Ok(())
+  }
 
-    let default_id = if cfg.default_server_id.is_empty() {
-      order[0].clone()
-    } else if servers.contains_key(&cfg.default_server_id) {
-      cfg.default_server_id
-    } else {
-      return Err(anyhow!(
-        "defaultServerId {:?} not found in servers",
-        cfg.default_server_id
-      ));
-    };
+  /// Validated `proxyPrefixes` without trailing slashes.
+  fn proxy_prefixes(&self) -> Vec<String> {
+    self
+      .proxy_prefixes
+      .iter()
+      .map(|v| v.trim().trim_end_matches('/').to_string())
+      .collect()
+  }
 
-    Ok(Self { default_id, servers, order })
+  fn max_upload_bytes(&self) -> usize {
+    self.max_upload_bytes.unwrap_or(MAX_BODY_BYTES)
   }
 
-  fn selected_id<'a>(&'a self, jar: &'a CookieJar) -> &'a str {
-    if let Some(cookie) = jar.get(COOKIE_SELECTED_SERVER) {
-      let id = cookie.value().trim();
-      if !id.is_empty() && self.servers.contains_key(id) {
-        return id;
-      }
+  fn max_config_body_bytes(&self) -> usize {
+    self.max_config_body_bytes.unwrap_or(MAX_CONFIG_BODY_BYTES)
+  }
+
+  fn max_uri_bytes(&self) -> usize {
+    self.max_uri_bytes.unwrap_or(MAX_URI_BYTES)
+  }
+
+  fn max_select_body_bytes(&self) -> usize {
+    self.max_select_body_bytes.unwrap_or(MAX_SELECT_BODY_BYTES)
+  }
+
+  fn qbit_login_attempts(&self) -> u32 {
+    self.qbit_login_attempts.unwrap_or(1)
+  }
+
+  /// Returns the base path in `/prefix/` form, or `None` when served at `/`.
+  fn base_path(&self) -> Option<String> {
+    let v = self.base_path.as_deref()?.trim().trim_matches('/');
+    if v.is_empty() {
+      return None;
     }
-    &self.default_id
+    Some(format!("/{v}/"))
   }
 
-  fn pick<'a>(&'a self, jar: &'a CookieJar) -> &'a ServerEntry {
-    let id = self.selected_id(jar);
-    self.servers.get(id).expect("catalog validated")
+  fn upstream_proxy(&self) -> Option<Url> {
+    self.upstream_proxy.as_deref().and_then(|v| parse_proxy_url(v).ok())
   }
-}
 
-#[derive(Clone)]
-struct AppState {
-  catalog: Arc<RwLock<Catalog>>,
-  qbit: Arc<QbitSessions>,
-  client: reqwest::Client,
-  config_path: Arc<PathBuf>,
-}
+  fn min_tls_version(&self) -> Option<reqwest::tls::Version> {
+    self.min_tls_version.as_deref().and_then(|v| parse_tls_version(v).ok())
+  }
 
-struct QbitSession {
-  cookie: Option<String>,
-}
+  fn status_probe_timeout(&self) -> Duration {
+    self
+      .status_probe_timeout_ms
+      .map(Duration::from_millis)
+      .unwrap_or(STATUS_PROBE_TIMEOUT)
+  }
 
-struct QbitSessions {
-  sessions: Mutex<HashMap<String, Arc<Mutex<QbitSession>>>>,
-  client: reqwest::Client,
-}
+  fn inspect_body_max_bytes(&self) -> usize {
+    self.inspect_body_max_bytes.unwrap_or(DEFAULT_INSPECT_BODY_MAX_BYTES)
+  }
 
-impl QbitSessions {
-  fn new() -> Result<Self> {
-    let client = reqwest::Client::builder()
-      .timeout(Duration::from_secs(12))
-      .redirect(Policy::none())
-      .build()
-      .context("build qB http client")?;
+  fn max_upstream_timeout(&self) -> Duration {
+    self
+      .max_upstream_timeout_secs
+      .map(Duration::from_secs)
+      .unwrap_or(MAX_UPSTREAM_TIMEOUT)
+  }
 
-    Ok(Self {
-      sessions: Mutex::new(HashMap::new()),
-      client,
-    })
+  fn session_idle(&self) -> Option<Duration> {
+    self.session_idle_secs.filter(|v| *v > 0).map(Duration::from_secs)
   }
 
-  async fn session(&self, id: &str) -> Arc<Mutex<QbitSession>> {
-    let mut map = self.sessions.lock().await;
-    map
-      .entry(id.to_string())
-      .or_insert_with(|| Arc::new(Mutex::new(QbitSession { cookie: None })))
-      .clone()
+  fn status_concurrency(&self) -> usize {
+    self.status_concurrency.unwrap_or(STATUS_CONCURRENCY)
   }
 
-  async fn clear(&self) {
-    self.sessions.lock().await.clear();
+  fn headless(&self) -> bool {
+    self.headless.unwrap_or_else(|| {
+      let v = env_or_default("HEADLESS", "");
+      matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on")
+    })
   }
 
-  async fn ensure_cookie(&self, entry: &ServerEntry, force: bool) -> Result<String> {
-    if entry.cfg.username.is_empty() && entry.cfg.password.is_empty() {
-      return Err(anyhow!(
-        "qBittorrent server requires username/password in config"
-      ));
+  /// `httpsOrigin` without a trailing slash; must be an `https` URL without
+  /// a path.
+  fn https_origin_url(&self) -> Result<Option<String>> {
+    let Some(raw) = &self.https_origin else {
+      return Ok(None);
+    };
+    let url = Url::parse(raw.trim()).context("parse URL")?;
+    if url.scheme() != "https" || url.host_str().is_none() {
+      return Err(anyhow!("must be an https:// URL"));
+    }
+    if url.path() != "/" || url.query().is_some() || url.fragment().is_some() {
+      return Err(anyhow!("must not have a path, query or fragment"));
     }
+    Ok(Some(url.as_str().trim_end_matches('/').to_string()))
+  }
 
-    let session = self.session(&entry.cfg.id).await;
-    let mut guard = session.lock().await;
+  fn config_editable(&self) -> bool {
+    self.config_editable.unwrap_or(true)
+  }
 
-    if let Some(cookie) = guard.cookie.clone() {
-      if !force {
-        return Ok(cookie);
-      }
+  fn reachable_failure_threshold(&self) -> u32 {
+    self.reachable_failure_threshold.unwrap_or(1)
+  }
+
+  fn health_interval(&self) -> Option<Duration> {
+    match self.health_interval_secs.unwrap_or(HEALTH_INTERVAL_SECS) {
+      0 => None,
+      secs => Some(Duration::from_secs(secs)),
     }
+  }
+}
 
-    let login_url = join_url(&entry.base, "/api/v2/auth/login")?;
-    let origin = entry.origin.clone();
-    let referer = format!("{}/", origin);
+#[derive(Debug, Clone)]
+struct ServerEntry {
+  cfg: ServerConfig,
+  base: Url,
+  origin: String,
+  socket: Option<PathBuf>,
+  /// Dedicated client for servers that need their own transport (Unix
+  /// socket, client certificate, custom CA, proxy override); `None` uses the
+  /// shared client.
+  client: Option<reqwest::Client>,
+  /// Effective upstream proxy; status probes dial it instead of `base`.
+  proxy: Option<Url>,
+  login_attempts: u32,
+  slow_request_threshold: Option<Duration>,
+  max_upstream_timeout: Duration,
+  /// Effective `inspectBodyMaxBytes`.
+  inspect_body_max_bytes: usize,
+  /// Effective body caps: `maxUploadBytes` of the server, else the global
+  /// `maxUploadBytes` / `maxStreamUploadBytes`.
+  max_upload_bytes: u64,
+  max_stream_upload_bytes: Option<u64>,
+  /// Compiled `allowedPaths` / `deniedPaths`; `None` when both are empty.
+  path_rules: Option<Arc<PathRules>>,
+  /// Effective `userAgent`; `None` forwards the client's.
+  user_agent: Option<HeaderValue>,
+  /// Parsed `stripRequestHeaders`.
+  strip_request_headers: Vec<HeaderName>,
+  /// Parsed `upstreamHost`.
+  host: Option<HeaderValue>,
+  /// Compiled `responseCache`.
+  cache_rules: Arc<[CacheRule]>,
+  /// Compiled `destructivePaths`.
+  destructive_paths: Option<Arc<PathMatcher>>,
+  /// Worker processes of a `command` backend.
+  command: Option<Arc<CommandPool>>,
+  /// Admission queue for `maxConcurrentRequests`.
+  queue: Option<Arc<FairQueue>>,
+}
 
-    let resp = self
-      .client
-      .post(login_url)
-      .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
-      .header("Origin", &origin)
-      .header("Referer", &referer)
-      .form(&[
-        ("username", entry.cfg.username.clone()),
-        ("password", entry.cfg.password.clone()),
-      ])
-      .send()
-      .await
-      .context("qB login request failed")?;
+/// Caps in-flight requests to one server. When full, waiting requests are
+/// grouped by client IP and admitted one client at a time in rotation, so a
+/// bulk job from one client cannot starve interactive users.
+#[derive(Debug)]
+struct FairQueue {
+  limit: usize,
+  state: std::sync::Mutex<FairQueueState>,
+}
 
-    let status = resp.status();
-    let headers = resp.headers().clone();
-    let body = resp
-      .bytes()
-      .await
-      .unwrap_or_else(|_| Bytes::from_static(b""));
+#[derive(Debug, Default)]
+struct FairQueueState {
+  active: usize,
+  /// Clients with waiting requests, in admission order.
+  waiting: VecDeque<(IpAddr, VecDeque<oneshot::Sender<FairPermit>>)>,
+  depth: usize,
+}
 
-    if status != StatusCode::OK {
-      let text = String::from_utf8_lossy(&body).trim().to_string();
-      return Err(anyhow!("qB login failed: status={} body={:?}", status, text));
-    }
-    if !String::from_utf8_lossy(&body).contains("Ok") {
-      let text = String::from_utf8_lossy(&body).trim().to_string();
-      return Err(anyhow!("qB login failed: body={:?}", text));
+impl FairQueueState {
+  /// Pops the oldest waiter of the next client and moves that client to the
+  /// back of the rotation.
+  fn pop_next(&mut self) -> Option<oneshot::Sender<FairPermit>> {
+    let (client, mut waiters) = self.waiting.pop_front()?;
+    let next = waiters.pop_front().expect("clients without waiters are removed");
+    if !waiters.is_empty() {
+      self.waiting.push_back((client, waiters));
     }
+    self.depth -= 1;
+    Some(next)
+  }
+}
 
-    let cookies = extract_set_cookie_pairs(&headers);
-    if cookies.is_empty() {
-      return Err(anyhow!("qB login did not set cookies"));
+/// A slot in a [`FairQueue`]; dropping it hands the slot to the next waiter.
+#[derive(Debug)]
+struct FairPermit {
+  queue: Option<Arc<FairQueue>>,
+}
+
+impl FairQueue {
+  fn new(limit: usize) -> Self {
+    Self {
+      limit,
+      state: std::sync::Mutex::new(FairQueueState::default()),
     }
+  }
 
-    let cookie = cookies.join("; ");
-    guard.cookie = Some(cookie.clone());
-    Ok(cookie)
+  /// Requests waiting for a slot.
+  fn depth(&self) -> usize {
+    self.state.lock().expect("queue lock").depth
+  }
+
+  async fn acquire(self: &Arc<Self>, client: IpAddr) -> FairPermit {
+    let rx = {
+      let mut state = self.state.lock().expect("queue lock");
+      if state.active < self.limit {
+        state.active += 1;
+        return FairPermit {
+          queue: Some(self.clone()),
+        };
+      }
+      let (tx, rx) = oneshot::channel();
+      match state.waiting.iter_mut().find(|(ip, _)| *ip == client) {
+        Some((_, waiters)) => waiters.push_back(tx),
+        None => state.waiting.push_back((client, VecDeque::from([tx]))),
+      }
+      state.depth += 1;
+      rx
+    };
+    // The sender is only dropped after sending.
+    rx.await.expect("queued request admitted")
   }
 }
 
-#[derive(Debug, serde::Serialize)]
-#[serde(rename_all = "camelCase")]
-struct ServerPublic {
-  id: String,
-  name: String,
-  #[serde(rename = "type")]
-  kind: BackendType,
-  base_url: String,
-  #[serde(skip_serializing_if = "Option::is_none")]
-  latency_ms: Option<u64>,
-  reachable: bool,
+impl Drop for FairPermit {
+  fn drop(&mut self) {
+    let Some(queue) = self.queue.take() else {
+      return;
+    };
+    let mut next = FairPermit {
+      queue: Some(queue.clone()),
+    };
+    let mut state = queue.state.lock().expect("queue lock");
+    // Waiters that gave up return the permit; try the next one.
+    while let Some(tx) = state.pop_next() {
+      match tx.send(next) {
+        Ok(()) => return,
+        Err(permit) => next = permit,
+      }
+    }
+    state.active -= 1;
+    next.queue = None;
+  }
 }
 
+/// One request handed to a `command` backend, written as a single JSON line.
 #[derive(Debug, serde::Serialize)]
-#[serde(rename_all = "camelCase")]
-struct StatusResponse {
-  schema: u32,
-  selected_id: String,
-  servers: Vec<ServerPublic>,
+struct CommandRequest<'a> {
+  method: &'a str,
+  path: &'a str,
+  headers: BTreeMap<&'a str, &'a str>,
+  /// Base64.
+  body: String,
 }
 
+/// The reply a `command` backend writes back, as a single JSON line.
 #[derive(Debug, serde::Deserialize)]
-struct SelectRequest {
-  id: String,
+struct CommandResponse {
+  status: u16,
+  #[serde(default)]
+  headers: BTreeMap<String, String>,
+  /// Base64.
+  #[serde(default)]
+  body: String,
+}
+
+/// Long-lived worker processes of a `command` backend, each handling one
+/// request at a time. A worker that fails or times out is killed instead of
+/// being reused.
+#[derive(Debug)]
+struct CommandPool {
+  argv: Vec<String>,
+  idle: Mutex<Vec<CommandWorker>>,
+  permits: Semaphore,
+}
+
+#[derive(Debug)]
+struct CommandWorker {
+  child: tokio::process::Child,
+  stdin: tokio::process::ChildStdin,
+  stdout: tokio::io::BufReader<tokio::process::ChildStdout>,
+}
+
+impl CommandPool {
+  fn new(argv: Vec<String>) -> Self {
+    Self {
+      argv,
+      idle: Mutex::new(Vec::new()),
+      permits: Semaphore::new(COMMAND_POOL_SIZE),
+    }
+  }
+
+  fn spawn(&self) -> Result<CommandWorker> {
+    let (program, args) = self.argv.split_first().ok_or_else(|| anyhow!("command is empty"))?;
+    let mut child = tokio::process::Command::new(program)
+      .args(args)
+      .stdin(std::process::Stdio::piped())
+      .stdout(std::process::Stdio::piped())
+      .kill_on_drop(true)
+      .spawn()
+      .with_context(|| format!("spawn {program:?}"))?;
+    let stdin = child.stdin.take().expect("piped stdin");
+    let stdout = tokio::io::BufReader::new(child.stdout.take().expect("piped stdout"));
+    Ok(CommandWorker { child, stdin, stdout })
+  }
+
+  /// Takes a live idle worker, spawning one when there is none.
+  async fn checkout(&self) -> Result<CommandWorker> {
+    let mut idle = self.idle.lock().await;
+    while let Some(mut worker) = idle.pop() {
+      if matches!(worker.child.try_wait(), Ok(None)) {
+        return Ok(worker);
+      }
+    }
+    drop(idle);
+    self.spawn()
+  }
+
+  /// Ensures a worker is running.
+  async fn ping(&self) -> Result<()> {
+    let _permit = self.permits.acquire().await.expect("semaphore is never closed");
+    let worker = self.checkout().await?;
+    self.idle.lock().await.push(worker);
+    Ok(())
+  }
+
+  async fn call(&self, req: &CommandRequest<'_>) -> Result<CommandResponse> {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+    let _permit = self.permits.acquire().await.expect("semaphore is never closed");
+    let mut worker = self.checkout().await?;
+    let mut line = serde_json::to_vec(req).context("encode command request")?;
+    line.push(b'\n');
+    let exchange = async {
+      worker.stdin.write_all(&line).await.context("write to command")?;
+      worker.stdin.flush().await.context("write to command")?;
+      let mut out = Vec::new();
+      (&mut worker.stdout)
+        .take(MAX_COMMAND_RESPONSE_BYTES as u64)
+        .read_until(b'\n', &mut out)
+        .await
+        .context("read from command")?;
+      match out.last() {
+        Some(b'\n') => {}
+        None => return Err(anyhow!("command exited")),
+        Some(_) if out.len() >= MAX_COMMAND_RESPONSE_BYTES => {
+          return Err(anyhow!("command response too large"));
+        }
+        Some(_) => return Err(anyhow!("command exited mid-response")),
+      }
+      serde_json::from_slice::<CommandResponse>(&out).context("invalid command response")
+    };
+    let resp = timeout_at(Instant::now() + PROXY_TIMEOUT, exchange)
+      .await
+      .map_err(|_| anyhow!("command timed out"))??;
+    self.idle.lock().await.push(worker);
+    Ok(resp)
+  }
+}
+
+#[derive(Debug)]
+struct CacheRule {
+  matcher: PathMatcher,
+  ttl: Duration,
+}
+
+impl ServerEntry {
+  /// TTL of the first `responseCache` rule matching `path`.
+  fn cache_ttl(&self, path: &str) -> Option<Duration> {
+    self.cache_rules.iter().find(|r| r.matcher.is_match(path)).map(|r| r.ttl)
+  }
+}
+
+#[derive(Debug)]
+struct PathRules {
+  allow: Option<PathMatcher>,
+  deny: Option<PathMatcher>,
+}
+
+#[derive(Debug)]
+enum PathMatcher {
+  Glob(globset::GlobSet),
+  Regex(regex::RegexSet),
+}
+
+impl PathMatcher {
+  fn compile(kind: PathMatch, patterns: &[String]) -> Result<Option<Self>> {
+    if patterns.is_empty() {
+      return Ok(None);
+    }
+    let matcher = match kind {
+      PathMatch::Glob => {
+        let mut set = globset::GlobSetBuilder::new();
+        for pattern in patterns {
+          let glob = globset::GlobBuilder::new(pattern.trim())
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("invalid glob {:?}", pattern))?;
+          set.add(glob);
+        }
+        Self::Glob(set.build().context("build glob set")?)
+      }
+      PathMatch::Regex => Self::Regex(
+        regex::RegexSet::new(patterns.iter().map(|v| v.trim())).context("invalid regex")?,
+      ),
+    };
+    Ok(Some(matcher))
+  }
+
+  fn is_match(&self, path: &str) -> bool {
+    match self {
+      Self::Glob(set) => set.is_match(path),
+      Self::Regex(set) => set.is_match(path),
+    }
+  }
+}
+
+impl PathRules {
+  fn compile(cfg: &ServerConfig) -> Result<Option<Self>> {
+    let kind = cfg.path_match.unwrap_or(PathMatch::Glob);
+    let allow = PathMatcher::compile(kind, &cfg.allowed_paths).context("allowedPaths")?;
+    let deny = PathMatcher::compile(kind, &cfg.denied_paths).context("deniedPaths")?;
+    if allow.is_none() && deny.is_none() {
+      return Ok(None);
+    }
+    Ok(Some(Self { allow, deny }))
+  }
+
+  fn allows(&self, path: &str) -> bool {
+    if self.deny.as_ref().is_some_and(|m| m.is_match(path)) {
+      return false;
+    }
+    self.allow.as_ref().is_none_or(|m| m.is_match(path))
+  }
+}
+
+#[derive(Debug)]
+struct Catalog {
+  default_id: String,
+  default_qbit_id: Option<String>,
+  default_trans_id: Option<String>,
+  servers: HashMap<String, ServerEntry>,
+  order: Vec<String>,
+  settings: GatewaySettings,
+  response_headers: Arc<ResponseHeaderRules>,
+  /// Set when `accessLog` is on.
+  access_log: Option<Arc<LogRedactor>>,
+}
+
+/// Masks secret query, form and JSON fields before they reach the access log.
+#[derive(Debug)]
+struct LogRedactor {
+  keys: Vec<String>,
+}
+
+impl LogRedactor {
+  fn from_settings(settings: &GatewaySettings) -> Option<Self> {
+    if settings.access_log != Some(true) {
+      return None;
+    }
+    let keys = match &settings.log_redact_keys {
+      Some(keys) => keys
+        .iter()
+        .map(|k| k.trim().to_ascii_lowercase())
+        .filter(|k| !k.is_empty())
+        .collect(),
+      None => DEFAULT_LOG_REDACT_KEYS.iter().map(|k| k.to_string()).collect(),
+    };
+    Some(Self { keys })
+  }
+
+  fn is_secret(&self, name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    self.keys.iter().any(|k| name.contains(k.as_str()))
+  }
+
+  /// Re-encodes `application/x-www-form-urlencoded` data (or a query string)
+  /// with secret values replaced; values holding JSON are masked inside.
+  fn form(&self, raw: &[u8]) -> String {
+    let mut out = url::form_urlencoded::Serializer::new(String::new());
+    for (name, value) in url::form_urlencoded::parse(raw) {
+      if self.is_secret(&name) {
+        out.append_pair(&name, "***");
+        continue;
+      }
+      match serde_json::from_str::<serde_json::Value>(&value) {
+        Ok(mut json) if json.is_object() || json.is_array() => {
+          self.json(&mut json);
+          out.append_pair(&name, &json.to_string());
+        }
+        _ => {
+          out.append_pair(&name, &value);
+        }
+      }
+    }
+    out.finish()
+  }
+
+  fn json(&self, value: &mut serde_json::Value) {
+    match value {
+      serde_json::Value::Object(map) => {
+        for (name, v) in map.iter_mut() {
+          if self.is_secret(name) {
+            *v = serde_json::Value::from("***");
+          } else {
+            self.json(v);
+          }
+        }
+      }
+      serde_json::Value::Array(items) => items.iter_mut().for_each(|v| self.json(v)),
+      _ => {}
+    }
+  }
+}
+
+/// `http://host:443` and `https://host:80` are almost always typos that
+/// otherwise surface as opaque connection errors.
+fn warn_scheme_port_mismatch(cfg: &ConfigFile) {
+  for server in &cfg.servers {
+    let Ok(url) = Url::parse(server.base_url.trim()) else {
+      continue;
+    };
+    let suggested = match (url.scheme(), url.port()) {
+      ("http", Some(443)) => "https",
+      ("https", Some(80)) => "http",
+      _ => continue,
+    };
+    tracing::warn!(
+      server = %server.id,
+      base_url = %server.base_url,
+      "baseUrl scheme does not match its port; did you mean {suggested}://?"
+    );
+  }
+}
+
+/// Parsed `stripResponseHeaders` / `addResponseHeaders`.
+#[derive(Debug, Default)]
+struct ResponseHeaderRules {
+  strip: Vec<HeaderName>,
+  add: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl ResponseHeaderRules {
+  fn from_settings(settings: &GatewaySettings) -> Result<Self> {
+    let mut out = Self::default();
+    for name in &settings.strip_response_headers {
+      let parsed = HeaderName::from_bytes(name.trim().as_bytes())
+        .with_context(|| format!("stripResponseHeaders: invalid header name {:?}", name))?;
+      out.strip.push(parsed);
+    }
+    for (name, value) in &settings.add_response_headers {
+      let parsed = HeaderName::from_bytes(name.trim().as_bytes())
+        .with_context(|| format!("addResponseHeaders: invalid header name {:?}", name))?;
+      let value = HeaderValue::from_str(value)
+        .with_context(|| format!("addResponseHeaders: invalid value for {:?}", name))?;
+      out.add.push((parsed, value));
+    }
+    Ok(out)
+  }
+
+  fn apply(&self, headers: &mut HeaderMap) {
+    for name in &self.strip {
+      headers.remove(name);
+    }
+    for (name, value) in &self.add {
+      headers.insert(name.clone(), value.clone());
+    }
+  }
+}
+
+impl Catalog {
+  fn load(path: &Path) -> Result<Self> {
+    let layers = config_layers(path);
+    if layers.len() == 1 {
+      let raw = std::fs::read(path).with_context(|| format!("read config: {}", path.display()))?;
+      let raw = decompress_config(raw).with_context(|| format!("decompress {}", path.display()))?;
+      let cfg: ConfigFile =
+        serde_json::from_slice(&raw).context("parse config")?;
+      warn_scheme_port_mismatch(&cfg);
+      return Self::from_config(cfg);
+    }
+
+    let mut merged: Option<serde_json::Value> = None;
+    for layer in &layers {
+      let raw = match std::fs::read(layer) {
+        Ok(v) => v,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+          tracing::warn!(path = %layer.display(), "config file not found, skipping");
+          continue;
+        }
+        Err(err) => {
+          return Err(err).with_context(|| format!("read config: {}", layer.display()));
+        }
+      };
+      let raw = decompress_config(raw).with_context(|| format!("decompress {}", layer.display()))?;
+      let value: serde_json::Value = serde_json::from_slice(&raw)
+        .with_context(|| format!("parse config: {}", layer.display()))?;
+      merged = Some(match merged {
+        None => value,
+        Some(base) => merge_config_layer(base, value)
+          .with_context(|| format!("merge config: {}", layer.display()))?,
+      });
+    }
+    let merged = merged.ok_or_else(|| anyhow!("no config file found in {}", path.display()))?;
+    let cfg: ConfigFile = serde_json::from_value(merged).context("parse config")?;
+    warn_scheme_port_mismatch(&cfg);
+    Self::from_config(cfg)
+  }
+
+  fn from_config(mut cfg: ConfigFile) -> Result<Self> {
+    if cfg.servers.is_empty() {
+      return Err(anyhow!("config.servers is empty"));
+    }
+
+    cfg.settings.validate()?;
+    cfg.default_server_id = cfg.default_server_id.trim().to_string();
+
+    let mut servers = HashMap::with_capacity(cfg.servers.len());
+    let mut order = Vec::with_capacity(cfg.servers.len());
+
+    for mut s in cfg.servers.drain(..) {
+      s.id = s.id.trim().to_string();
+      s.name = s.name.trim().to_string();
+      s.base_url = s.base_url.trim().to_string();
+      s.username = s.username.trim().to_string();
+      s.password = s.password.trim().to_string();
+      trim_optional(&mut s.client_cert_path);
+      trim_optional(&mut s.client_key_path);
+      trim_optional(&mut s.ca_cert_path);
+      trim_optional(&mut s.upstream_proxy);
+      trim_optional(&mut s.user_agent);
+      trim_optional(&mut s.upstream_host);
+      trim_optional(&mut s.color);
+      trim_optional(&mut s.icon);
+
+      if s.id.is_empty() {
+        return Err(anyhow!("server.id is required"));
+      }
+      if s.name.is_empty() {
+        s.name = s.id.clone();
+      }
+      if s.kind == BackendType::Command {
+        if s.command.first().is_none_or(|v| v.trim().is_empty()) {
+          return Err(anyhow!("server {:?}: command is required", s.id));
+        }
+      } else if !s.command.is_empty() {
+        return Err(anyhow!("server {:?}: command requires type \"command\"", s.id));
+      } else if s.base_url.is_empty() {
+        return Err(anyhow!("server {:?}: baseUrl is required", s.id));
+      }
+      if servers.contains_key(&s.id) {
+        return Err(anyhow!("duplicate server id: {:?}", s.id));
+      }
+      if let Some(color) = &s.color {
+        if !is_hex_color(color) {
+          return Err(anyhow!("server {:?}: invalid color {:?}", s.id, color));
+        }
+      }
+      match s.auth {
+        Some(_) if !matches!(s.kind, BackendType::Qbit | BackendType::Auto) => {
+          return Err(anyhow!("server {:?}: auth is only supported for qBittorrent", s.id));
+        }
+        Some(QbitAuth::Password) if s.username.is_empty() && s.password.is_empty() => {
+          return Err(anyhow!("server {:?}: auth \"password\" requires username/password", s.id));
+        }
+        _ => {}
+      }
+
+      let (base, socket) = if s.kind == BackendType::Command {
+        // Never dialed; keeps `origin` and the URL helpers well-defined.
+        (Url::parse("http://localhost/").expect("valid url"), None)
+      } else if let Some((socket, path)) = parse_unix_base_url(&s.base_url) {
+        if !matches!(s.kind, BackendType::Trans | BackendType::Auto) {
+          return Err(anyhow!(
+            "server {:?}: unix socket baseUrl is only supported for Transmission",
+            s.id
+          ));
+        }
+        let base = Url::parse(&format!("http://localhost{path}"))
+          .with_context(|| format!("server {:?}: invalid baseUrl {:?}", s.id, s.base_url))?;
+        (base, Some(socket))
+      } else {
+        let base = Url::parse(&s.base_url)
+          .with_context(|| format!("server {:?}: invalid baseUrl {:?}", s.id, s.base_url))?;
+        if base.scheme().is_empty() || base.host_str().is_none() {
+          return Err(anyhow!("server {:?}: invalid baseUrl {:?}", s.id, s.base_url));
+        }
+        (base, None)
+      };
+      let base = normalize_base_url(base);
+      let proxy = match s.upstream_proxy.as_deref() {
+        _ if socket.is_some() => {
+          if s.upstream_proxy.is_some() {
+            return Err(anyhow!(
+              "server {:?}: upstreamProxy is not supported with a unix socket baseUrl",
+              s.id
+            ));
+          }
+          None
+        }
+        Some("none") => None,
+        Some(v) => Some(
+          parse_proxy_url(v).with_context(|| format!("server {:?}: upstreamProxy", s.id))?,
+        ),
+        None => cfg.settings.upstream_proxy(),
+      };
+      let min_tls = cfg.settings.min_tls_version();
+      let client = build_server_client(&s, socket.as_deref(), proxy.as_ref(), min_tls)
+        .with_context(|| format!("server {:?}: build http client", s.id))?;
+      let path_rules = PathRules::compile(&s).with_context(|| format!("server {:?}", s.id))?;
+      if !s.destructive_rpc_methods.is_empty()
+        && !matches!(s.kind, BackendType::Trans | BackendType::Auto)
+      {
+        return Err(anyhow!(
+          "server {:?}: destructiveRpcMethods is only supported for Transmission",
+          s.id
+        ));
+      }
+      let destructive_paths =
+        PathMatcher::compile(s.path_match.unwrap_or(PathMatch::Glob), &s.destructive_paths)
+          .with_context(|| format!("server {:?}: destructivePaths", s.id))?;
+      if s.max_upload_bytes == Some(0) {
+        return Err(anyhow!("server {:?}: maxUploadBytes must be greater than 0", s.id));
+      }
+      if s.max_concurrent_requests == Some(0) {
+        return Err(anyhow!("server {:?}: maxConcurrentRequests must be greater than 0", s.id));
+      }
+      if s.rewrite.iter().any(|rule| rule.from.is_empty()) {
+        return Err(anyhow!("server {:?}: rewrite: from must not be empty", s.id));
+      }
+      let mut cache_rules = Vec::with_capacity(s.response_cache.len());
+      for rule in &s.response_cache {
+        if rule.ttl_secs == 0 {
+          return Err(anyhow!("server {:?}: responseCache: ttlSecs must be greater than 0", s.id));
+        }
+        let kind = s.path_match.unwrap_or(PathMatch::Glob);
+        let matcher = PathMatcher::compile(kind, std::slice::from_ref(&rule.path))
+          .with_context(|| format!("server {:?}: responseCache", s.id))?
+          .expect("one pattern");
+        cache_rules.push(CacheRule {
+          matcher,
+          ttl: Duration::from_secs(rule.ttl_secs),
+        });
+      }
+      let user_agent = match s.user_agent.as_deref().or(cfg.settings.user_agent.as_deref()) {
+        Some(v) => Some(
+          HeaderValue::from_str(v.trim())
+            .with_context(|| format!("server {:?}: userAgent: invalid header value", s.id))?,
+        ),
+        None => None,
+      };
+      let mut strip_request_headers = Vec::with_capacity(s.strip_request_headers.len());
+      for name in &s.strip_request_headers {
+        let parsed = HeaderName::from_bytes(name.trim().as_bytes()).with_context(|| {
+          format!("server {:?}: stripRequestHeaders: invalid header name {:?}", s.id, name)
+        })?;
+        strip_request_headers.push(parsed);
+      }
+      for (key, value) in &s.query_params {
+        if key.is_empty() || !is_url_safe(key) || !is_url_safe(value) {
+          return Err(anyhow!(
+            "server {:?}: queryParams: {:?} must use only letters, digits and -._~",
+            s.id,
+            key
+          ));
+        }
+      }
+
+      let host = match &s.upstream_host {
+        Some(v) => Some(parse_upstream_host(v).with_context(|| {
+          format!("server {:?}: upstreamHost: invalid host {:?}", s.id, v)
+        })?),
+        None => None,
+      };
+
+      // qBittorrent checks Origin against Host, so a Host override moves
+      // the origin along with it.
+      let origin = match &s.upstream_host {
+        Some(v) => Url::parse(&format!("{}://{}", base.scheme(), v))
+          .map(|u| u.origin().ascii_serialization())
+          .with_context(|| format!("server {:?}: upstreamHost: invalid host {:?}", s.id, v))?,
+        None => {
+          let host_for_origin = format_host_only(base.host_str().unwrap());
+          if let Some(port) = base.port() {
+            format!("{}://{}:{}", base.scheme(), host_for_origin, port)
+          } else {
+            format!("{}://{}", base.scheme(), host_for_origin)
+          }
+        }
+      };
+      let command = (s.kind == BackendType::Command)
+        .then(|| Arc::new(CommandPool::new(s.command.clone())));
+      let queue = s.max_concurrent_requests.map(|limit| Arc::new(FairQueue::new(limit)));
+      let max_upload_bytes = s.max_upload_bytes;
+      let entry = ServerEntry {
+        cfg: s,
+        base,
+        origin,
+        socket,
+        client,
+        proxy,
+        login_attempts: cfg.settings.qbit_login_attempts(),
+        slow_request_threshold: cfg.settings.slow_request_threshold_ms.map(Duration::from_millis),
+        max_upstream_timeout: cfg.settings.max_upstream_timeout(),
+        inspect_body_max_bytes: cfg.settings.inspect_body_max_bytes(),
+        max_upload_bytes: max_upload_bytes.unwrap_or(cfg.settings.max_upload_bytes() as u64),
+        max_stream_upload_bytes: max_upload_bytes.or(cfg.settings.max_stream_upload_bytes),
+        path_rules: path_rules.map(Arc::new),
+        user_agent,
+        strip_request_headers,
+        host,
+        cache_rules: cache_rules.into(),
+        destructive_paths: destructive_paths.map(Arc::new),
+        command,
+        queue,
+      };
+      order.push(entry.cfg.id.clone());
+      servers.insert(entry.cfg.id.clone(), entry);
+    }
+
+    let default_id = if cfg.default_server_id.is_empty() {
+      order
+        .iter()
+        .find(|id| servers[*id].cfg.enabled)
+        .cloned()
+        .ok_or_else(|| anyhow!("all servers are disabled"))?
+    } else if let Some(entry) = servers.get(&cfg.default_server_id) {
+      if !entry.cfg.enabled {
+        return Err(anyhow!(
+          "defaultServerId {:?} refers to a disabled server",
+          cfg.default_server_id
+        ));
+      }
+      cfg.default_server_id
+    } else {
+      return Err(anyhow!(
+        "defaultServerId {:?} not found in servers",
+        cfg.default_server_id
+      ));
+    };
+
+    trim_optional(&mut cfg.default_qbit_id);
+    trim_optional(&mut cfg.default_trans_id);
+    for (key, kind, label, id) in [
+      ("defaultQbitId", BackendType::Qbit, "qbit", &cfg.default_qbit_id),
+      ("defaultTransId", BackendType::Trans, "trans", &cfg.default_trans_id),
+    ] {
+      let Some(id) = id else {
+        continue;
+      };
+      let Some(entry) = servers.get(id) else {
+        return Err(anyhow!("{key} {:?} not found in servers", id));
+      };
+      if entry.cfg.kind != kind && entry.cfg.kind != BackendType::Auto {
+        return Err(anyhow!("{key} {:?} is not a {label} server", id));
+      }
+      if !entry.cfg.enabled {
+        return Err(anyhow!("{key} {:?} refers to a disabled server", id));
+      }
+    }
+
+    Ok(Self {
+      default_id,
+      default_qbit_id: cfg.default_qbit_id,
+      default_trans_id: cfg.default_trans_id,
+      servers,
+      order,
+      response_headers: Arc::new(ResponseHeaderRules::from_settings(&cfg.settings)?),
+      access_log: LogRedactor::from_settings(&cfg.settings).map(Arc::new),
+      settings: cfg.settings,
+    })
+  }
+
+  /// Rebuilds the on-disk representation, servers in config order.
+  /// Discovered servers are left out and detected types revert to `auto`.
+  fn to_config(&self) -> ConfigFile {
+    ConfigFile {
+      default_server_id: self.default_id.clone(),
+      default_qbit_id: self.default_qbit_id.clone(),
+      default_trans_id: self.default_trans_id.clone(),
+      servers: self
+        .order
+        .iter()
+        .map(|id| &self.servers[id].cfg)
+        .filter(|cfg| !cfg.discovered)
+        .map(|cfg| ServerConfig {
+          kind: cfg.declared_kind(),
+          auto_detected: false,
+          ..cfg.clone()
+        })
+        .collect(),
+      settings: self.settings.clone(),
+    }
+  }
+
+  /// The default server for a backend type: the configured one, else the
+  /// first enabled server of that type in config order.
+  fn default_for(&self, kind: BackendType) -> Option<&ServerEntry> {
+    let configured = match kind {
+      BackendType::Qbit => self.default_qbit_id.as_ref(),
+      BackendType::Trans => self.default_trans_id.as_ref(),
+      BackendType::Command | BackendType::Auto => None,
+    };
+    if let Some(id) = configured {
+      return self.servers.get(id);
+    }
+    self
+      .order
+      .iter()
+      .map(|id| &self.servers[id])
+      .find(|entry| entry.cfg.kind == kind && entry.cfg.enabled)
+  }
+
+  fn type_defaults(&self) -> TypeDefaults {
+    TypeDefaults {
+      qbit: self.default_for(BackendType::Qbit).map(|e| e.cfg.id.clone()),
+      trans: self.default_for(BackendType::Trans).map(|e| e.cfg.id.clone()),
+    }
+  }
+
+  fn selected_id<'a>(&'a self, jar: &'a CookieJar, health: &HashMap<String, Health>) -> &'a str {
+    if let Some(cookie) = jar.get(COOKIE_SELECTED_SERVER) {
+      let id = cookie.value().trim();
+      if self.servers.get(id).is_some_and(|entry| entry.cfg.enabled) {
+        return id;
+      }
+    }
+    self.fallback_id(health)
+  }
+
+  /// The default server, or with `autoFailoverDefault` the first reachable
+  /// server (same backend type first) while the default is known to be down.
+  /// Servers without a health result count as unknown, not down.
+  fn fallback_id(&self, health: &HashMap<String, Health>) -> &str {
+    let down = |id: &str| health.get(id).is_some_and(|h| !h.reachable);
+    if !self.settings.auto_failover_default.unwrap_or(false) || !down(&self.default_id) {
+      return &self.default_id;
+    }
+    let Some(kind) = self.servers.get(&self.default_id).map(|entry| entry.cfg.kind) else {
+      return &self.default_id;
+    };
+    self
+      .order
+      .iter()
+      .filter_map(|id| self.servers.get(id))
+      .filter(|entry| entry.cfg.enabled)
+      .filter(|entry| health.get(&entry.cfg.id).is_some_and(|h| h.reachable))
+      .min_by_key(|entry| entry.cfg.kind != kind)
+      .map_or(&self.default_id, |entry| &entry.cfg.id)
+  }
+
+  fn pick<'a>(
+    &'a self,
+    jar: &'a CookieJar,
+    health: &HashMap<String, Health>,
+  ) -> Option<&'a ServerEntry> {
+    self.servers.get(self.selected_id(jar, health))
+  }
+
+  /// Resolves the server for a proxied request: an `X-TorrentMix-Server`
+  /// header wins over the selection cookie for that single request.
+  fn pick_for_request<'a>(
+    &'a self,
+    jar: &'a CookieJar,
+    headers: &HeaderMap,
+    health: &HashMap<String, Health>,
+  ) -> std::result::Result<&'a ServerEntry, &'static str> {
+    let Some(value) = headers.get(HEADER_SERVER_OVERRIDE) else {
+      return self.pick(jar, health).ok_or("selected server is not configured");
+    };
+    let id = value.to_str().map(str::trim).unwrap_or("");
+    if id.is_empty() {
+      return Err("invalid X-TorrentMix-Server header");
+    }
+    let entry = self.servers.get(id).ok_or("unknown server id")?;
+    if !entry.cfg.enabled {
+      return Err("server is disabled");
+    }
+    Ok(entry)
+  }
+
+  /// Like [`Self::pick_for_request`], but with `strictRouteTyping` enabled a
+  /// selection of the wrong backend type is replaced by that type's default.
+  fn pick_for_route<'a>(
+    &'a self,
+    jar: &'a CookieJar,
+    headers: &HeaderMap,
+    health: &HashMap<String, Health>,
+    path: &str,
+  ) -> std::result::Result<&'a ServerEntry, &'static str> {
+    let entry = self.pick_for_request(jar, headers, health)?;
+    if !self.settings.strict_route_typing.unwrap_or(false) {
+      return Ok(entry);
+    }
+    let expected = if path.starts_with("/transmission/") {
+      BackendType::Trans
+    } else if path.starts_with("/api/") {
+      BackendType::Qbit
+    } else {
+      return Ok(entry);
+    };
+    if entry.cfg.kind == expected {
+      return Ok(entry);
+    }
+    self
+      .default_for(expected)
+      .ok_or("no enabled server matches this route")
+  }
+}
+
+#[derive(Clone)]
+struct AppState {
+  catalog: Arc<RwLock<Catalog>>,
+  qbit: Arc<QbitSessions>,
+  client: reqwest::Client,
+  config_path: Arc<PathBuf>,
+  static_dir: Arc<PathBuf>,
+  index_path: Arc<PathBuf>,
+  maintenance: Arc<RwLock<Maintenance>>,
+  health: Arc<RwLock<HashMap<String, Health>>>,
+  favicons: Arc<Mutex<HashMap<String, CachedFavicon>>>,
+  /// Consecutive failed reachability probes per server id.
+  probe_failures: Arc<Mutex<HashMap<String, u32>>>,
+  /// Parsed `trustedProxies`.
+  trusted_proxies: Arc<[IpNet]>,
+  responses: Arc<Mutex<HashMap<ResponseCacheKey, CachedResponse>>>,
+  /// Coalesced `GET`s in flight, see [`join_flight`].
+  flights: Arc<std::sync::Mutex<HashMap<ResponseCacheKey, watch::Receiver<FlightResult>>>>,
+  /// Bumped after every health check; `None` when the monitor is disabled.
+  health_updates: Option<Arc<watch::Sender<u64>>>,
+  metrics: Arc<Metrics>,
+  /// Servers found by mDNS discovery, keyed by service instance name.
+  discovered: Arc<Mutex<BTreeMap<String, ServerConfig>>>,
+  /// Types detected for `auto` servers, keyed by `baseUrl`.
+  detected_types: Arc<Mutex<HashMap<String, BackendType>>>,
+  /// When detection last failed for an `auto` server's `baseUrl`.
+  detect_failures: Arc<Mutex<HashMap<String, Instant>>>,
+  /// Cancelled when the server shuts down; stops background tasks and
+  /// status streams.
+  shutdown: CancellationToken,
+}
+
+impl AppState {
+  /// Runs a background task until the server shuts down.
+  fn spawn_background<F>(&self, task: F)
+  where
+    F: std::future::Future<Output = ()> + Send + 'static,
+  {
+    tokio::spawn(self.shutdown.clone().run_until_cancelled_owned(task));
+  }
+
+  fn notify_health_update(&self) {
+    if let Some(tx) = &self.health_updates {
+      tx.send_modify(|generation| *generation = generation.wrapping_add(1));
+    }
+  }
+
+  /// Folds a probe result into the failure streak of `id`; the server is
+  /// reported unreachable once `threshold` probes in a row have failed.
+  async fn debounce_reachable(&self, id: &str, reachable: bool, threshold: u32) -> bool {
+    let mut failures = self.probe_failures.lock().await;
+    if reachable {
+      failures.remove(id);
+      return true;
+    }
+    let streak = failures.entry(id.to_string()).or_insert(0);
+    *streak = streak.saturating_add(1);
+    *streak < threshold
+  }
+}
+
+/// Upstream request counters, latency histograms and probe results per
+/// server, rendered in the Prometheus text format by
+/// `/__standalone__/metrics`. Status codes are bucketed into classes and
+/// only servers still in the catalog are rendered, so label cardinality stays
+/// bounded.
+#[derive(Debug, Default)]
+struct Metrics {
+  /// Keyed by server id and status class (`2xx` .. `5xx`, or `error`).
+  requests: std::sync::Mutex<BTreeMap<(String, &'static str), u64>>,
+  latency: std::sync::Mutex<BTreeMap<String, LatencyHistogram>>,
+  /// Last probe per server: dial latency and debounced reachability.
+  probes: std::sync::Mutex<BTreeMap<String, (Option<u64>, bool)>>,
+  /// qBittorrent re-authentications keyed by server id and reason.
+  reauths: std::sync::Mutex<BTreeMap<(String, &'static str), u64>>,
+}
+
+#[derive(Debug, Default)]
+struct LatencyHistogram {
+  /// Non-cumulative counts per [`LATENCY_BUCKETS`] bound.
+  buckets: [u64; LATENCY_BUCKETS.len()],
+  count: u64,
+  sum: f64,
+}
+
+/// Upper bounds (seconds) of the upstream latency histogram.
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0, 60.0];
+
+impl Metrics {
+  fn record_request(&self, server: &str, status: Option<StatusCode>, elapsed: Duration) {
+    let class = match status.map(|s| s.as_u16() / 100) {
+      Some(1) => "1xx",
+      Some(2) => "2xx",
+      Some(3) => "3xx",
+      Some(4) => "4xx",
+      Some(5) => "5xx",
+      _ => "error",
+    };
+    let mut requests = self.requests.lock().expect("metrics lock");
+    *requests.entry((server.to_string(), class)).or_insert(0) += 1;
+    drop(requests);
+
+    let secs = elapsed.as_secs_f64();
+    let mut latency = self.latency.lock().expect("metrics lock");
+    let h = latency.entry(server.to_string()).or_default();
+    if let Some(i) = LATENCY_BUCKETS.iter().position(|bound| secs <= *bound) {
+      h.buckets[i] += 1;
+    }
+    h.count += 1;
+    h.sum += secs;
+  }
+
+  /// `forbidden`: qBittorrent rejected a cached session with `403`;
+  /// `relogin`: a forced login replaced a cached session.
+  fn record_reauth(&self, server: &str, reason: &'static str) {
+    let mut reauths = self.reauths.lock().expect("metrics lock");
+    *reauths.entry((server.to_string(), reason)).or_insert(0) += 1;
+  }
+
+  fn record_probe(&self, server: &str, latency_ms: Option<u64>, reachable: bool) {
+    let mut probes = self.probes.lock().expect("metrics lock");
+    probes.insert(server.to_string(), (latency_ms, reachable));
+  }
+
+  /// Renders the metrics of the servers in `kinds` (id to backend type),
+  /// plus the current depth of their request queues.
+  fn render(
+    &self,
+    kinds: &HashMap<String, BackendType>,
+    queue_depths: &BTreeMap<String, usize>,
+  ) -> String {
+    use std::fmt::Write as _;
+
+    let labels = |id: &str| {
+      let kind = kinds.get(id).map_or("", |k| k.label());
+      format!("server_id=\"{}\",backend_type=\"{kind}\"", escape_label_value(id))
+    };
+    let mut out = String::new();
+
+    const REQUESTS: &str = "torrentmix_upstream_requests_total";
+    const DURATION: &str = "torrentmix_upstream_request_duration_seconds";
+    const REACHABLE: &str = "torrentmix_server_reachable";
+    const DIAL: &str = "torrentmix_server_dial_seconds";
+    const QUEUE: &str = "torrentmix_queue_depth";
+    const REAUTH: &str = "torrentmix_qbit_reauth_total";
+
+    let _ = writeln!(out, "# HELP {REQUESTS} Upstream requests by status class.");
+    let _ = writeln!(out, "# TYPE {REQUESTS} counter");
+    for ((id, class), n) in self.requests.lock().expect("metrics lock").iter() {
+      if kinds.contains_key(id) {
+        let labels = labels(id);
+        let _ = writeln!(out, "{REQUESTS}{{{labels},status_code=\"{class}\"}} {n}");
+      }
+    }
+
+    let _ = writeln!(out, "# HELP {DURATION} Upstream response time.");
+    let _ = writeln!(out, "# TYPE {DURATION} histogram");
+    for (id, h) in self.latency.lock().expect("metrics lock").iter() {
+      if !kinds.contains_key(id) {
+        continue;
+      }
+      let labels = labels(id);
+      let mut cumulative = 0;
+      for (bound, n) in LATENCY_BUCKETS.iter().zip(h.buckets) {
+        cumulative += n;
+        let _ = writeln!(out, "{DURATION}_bucket{{{labels},le=\"{bound}\"}} {cumulative}");
+      }
+      let _ = writeln!(out, "{DURATION}_bucket{{{labels},le=\"+Inf\"}} {}", h.count);
+      let _ = writeln!(out, "{DURATION}_sum{{{labels}}} {}", h.sum);
+      let _ = writeln!(out, "{DURATION}_count{{{labels}}} {}", h.count);
+    }
+
+    let probes = self.probes.lock().expect("metrics lock");
+    let _ = writeln!(out, "# HELP {REACHABLE} Result of the last reachability probe.");
+    let _ = writeln!(out, "# TYPE {REACHABLE} gauge");
+    for (id, (_, reachable)) in probes.iter().filter(|(id, _)| kinds.contains_key(*id)) {
+      let _ = writeln!(out, "{REACHABLE}{{{}}} {}", labels(id), u8::from(*reachable));
+    }
+    let _ = writeln!(out, "# HELP {QUEUE} Requests waiting for a maxConcurrentRequests slot.");
+    let _ = writeln!(out, "# TYPE {QUEUE} gauge");
+    for (id, depth) in queue_depths {
+      let _ = writeln!(out, "{QUEUE}{{{}}} {depth}", labels(id));
+    }
+    let _ = writeln!(out, "# HELP {REAUTH} qBittorrent session re-authentications by reason.");
+    let _ = writeln!(out, "# TYPE {REAUTH} counter");
+    for ((id, reason), n) in self.reauths.lock().expect("metrics lock").iter() {
+      if kinds.contains_key(id) {
+        let labels = labels(id);
+        let _ = writeln!(out, "{REAUTH}{{{labels},reason=\"{reason}\"}} {n}");
+      }
+    }
+    let _ = writeln!(out, "# HELP {DIAL} Connect time of the last successful probe.");
+    let _ = writeln!(out, "# TYPE {DIAL} gauge");
+    for (id, (latency_ms, _)) in probes.iter().filter(|(id, _)| kinds.contains_key(*id)) {
+      if let Some(ms) = latency_ms {
+        let _ = writeln!(out, "{DIAL}{{{}}} {}", labels(id), *ms as f64 / 1000.0);
+      }
+    }
+    out
+  }
+}
+
+fn escape_label_value(v: &str) -> String {
+  v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Server id, path with query, the client's `Accept-Encoding` (the body is
+/// cached as the upstream encoded it), and the client credentials that can
+/// change what the backend answers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ResponseCacheKey {
+  server: String,
+  path_and_query: String,
+  accept_encoding: Option<HeaderValue>,
+  cookie: Option<HeaderValue>,
+  authorization: Option<HeaderValue>,
+}
+
+impl ResponseCacheKey {
+  fn new(server: &str, uri: &Uri, headers: &HeaderMap) -> Self {
+    Self {
+      server: server.to_string(),
+      path_and_query: uri.path_and_query().map(|v| v.to_string()).unwrap_or_default(),
+      accept_encoding: headers.get(header::ACCEPT_ENCODING).cloned(),
+      cookie: headers.get(header::COOKIE).cloned(),
+      authorization: headers.get(header::AUTHORIZATION).cloned(),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct CachedResponse {
+  expires_at: Instant,
+  headers: HeaderMap,
+  body: Bytes,
+}
+
+/// Upstream response handed from the leader of a coalesced `GET` to the
+/// requests waiting on it.
+#[derive(Debug, Clone)]
+struct SharedResponse {
+  status: StatusCode,
+  headers: HeaderMap,
+  body: Bytes,
+}
+
+/// `None` while the leader's request runs; `Some(None)` when its response
+/// cannot be shared and every waiter sends its own request.
+type FlightResult = Option<Option<SharedResponse>>;
+
+enum Flight {
+  /// First request for its key; publishes the response for the others.
+  Leader(FlightLeader),
+  /// Another request with the same key finished first.
+  Shared(SharedResponse),
+  /// Nothing to share; forward independently.
+  Alone,
+}
+
+/// Removes its key from `flights` when dropped, so a leader that fails or
+/// is cancelled releases its waiters (they see the closed channel).
+struct FlightLeader {
+  flights: Arc<std::sync::Mutex<HashMap<ResponseCacheKey, watch::Receiver<FlightResult>>>>,
+  key: ResponseCacheKey,
+  tx: watch::Sender<FlightResult>,
+}
+
+impl FlightLeader {
+  fn publish(self, shared: Option<SharedResponse>) {
+    self.tx.send_replace(Some(shared));
+  }
+}
+
+impl Drop for FlightLeader {
+  fn drop(&mut self) {
+    self.flights.lock().expect("flight lock").remove(&self.key);
+  }
+}
+
+#[derive(Debug, Clone)]
+struct CachedFavicon {
+  fetched_at: Instant,
+  /// `None` when the backend had no icon or could not be reached.
+  icon: Option<Favicon>,
+}
+
+impl CachedFavicon {
+  fn is_fresh(&self) -> bool {
+    let ttl = if self.icon.is_some() { FAVICON_TTL } else { FAVICON_MISS_TTL };
+    self.fetched_at.elapsed() < ttl
+  }
+}
+
+#[derive(Debug, Clone)]
+struct Favicon {
+  content_type: HeaderValue,
+  body: Bytes,
+}
+
+/// Last background probe result for a server.
+#[derive(Debug, Clone, Copy)]
+struct Health {
+  latency_ms: Option<u64>,
+  reachable: bool,
+}
+
+struct QbitSession {
+  cookie: Option<String>,
+  /// `webapiVersion` reported by the server, refreshed on every login.
+  api_version: Option<String>,
+  /// Outcome of the most recent login, handed to requests that queued up
+  /// behind it instead of logging in again.
+  last_login: Option<std::result::Result<String, String>>,
+  /// When a proxied request last used `cookie`.
+  last_used: Option<Instant>,
+  /// When `cookie` was obtained.
+  logged_in_at: Option<SystemTime>,
+}
+
+/// Per-server session slot. `logins` counts finished logins and is read
+/// before taking the lock, so a waiter can tell whether a login completed
+/// while it was queued.
+struct QbitSlot {
+  logins: AtomicU64,
+  session: Mutex<QbitSession>,
+}
+
+struct QbitSessions {
+  sessions: Mutex<HashMap<String, Arc<QbitSlot>>>,
+  client: reqwest::Client,
+  metrics: Arc<Metrics>,
+}
+
+impl QbitSessions {
+  fn new(
+    proxy: Option<&Url>,
+    min_tls: Option<reqwest::tls::Version>,
+    metrics: Arc<Metrics>,
+  ) -> Result<Self> {
+    let client = with_upstream_proxy(with_min_tls(reqwest::Client::builder(), min_tls), proxy)?
+      .timeout(QBIT_LOGIN_TIMEOUT)
+      .redirect(Policy::none())
+      .http1_only()
+      .build()
+      .context("build qB http client")?;
+
+    Ok(Self {
+      sessions: Mutex::new(HashMap::new()),
+      client,
+      metrics,
+    })
+  }
+
+  async fn session(&self, id: &str) -> Arc<QbitSlot> {
+    let mut map = self.sessions.lock().await;
+    map
+      .entry(id.to_string())
+      .or_insert_with(|| {
+        Arc::new(QbitSlot {
+          logins: AtomicU64::new(0),
+          session: Mutex::new(QbitSession {
+            cookie: None,
+            api_version: None,
+            last_login: None,
+            last_used: None,
+            logged_in_at: None,
+          }),
+        })
+      })
+      .clone()
+  }
+
+  /// Takes over `Set-Cookie` values of a proxied response, since qBittorrent
+  /// may rotate its session cookie on any response. Only cookies already in
+  /// the session are updated, and an empty value drops the session. A slot
+  /// busy with a login is left alone; that login yields a fresh cookie.
+  async fn apply_rotation(&self, id: &str, headers: &HeaderMap) {
+    if !headers.contains_key(header::SET_COOKIE) {
+      return;
+    }
+    let slot = self.session(id).await;
+    let Ok(mut guard) = slot.session.try_lock() else {
+      return;
+    };
+    let Some(current) = guard.cookie.as_deref() else {
+      return;
+    };
+    let mut pairs = current
+      .split("; ")
+      .filter_map(|v| v.split_once('='))
+      .map(|(name, value)| (name.to_string(), value.to_string()))
+      .collect::<Vec<_>>();
+    let mut changed = false;
+    for pair in extract_set_cookie_pairs(headers).pairs {
+      let Some((name, value)) = pair.split_once('=') else {
+        continue;
+      };
+      let Some(known) = pairs.iter_mut().find(|(n, _)| n == name) else {
+        continue;
+      };
+      if value.is_empty() {
+        tracing::debug!(server = %id, cookie = %name, "qB session cookie cleared upstream");
+        guard.cookie = None;
+        guard.logged_in_at = None;
+        return;
+      }
+      if known.1 != value {
+        known.1 = value.to_string();
+        changed = true;
+      }
+    }
+    if !changed {
+      return;
+    }
+    tracing::debug!(server = %id, "qB session cookie rotated");
+    let cookie = pairs
+      .iter()
+      .map(|(name, value)| format!("{name}={value}"))
+      .collect::<Vec<_>>()
+      .join("; ");
+    guard.cookie = Some(cookie.clone());
+    guard.last_login = Some(Ok(cookie));
+  }
+
+  /// Moves the session of a renamed server to its new id.
+  async fn rename(&self, old_id: &str, new_id: &str) {
+    let mut map = self.sessions.lock().await;
+    if let Some(slot) = map.remove(old_id) {
+      map.insert(new_id.to_string(), slot);
+    }
+  }
+
+  /// Session state by server id, without cookie values. Slots locked by a
+  /// running login are reported as such instead of waiting for it.
+  async fn snapshot(&self) -> HashMap<String, SessionInfo> {
+    let map = self.sessions.lock().await;
+    map
+      .iter()
+      .map(|(id, slot)| {
+        let info = match slot.session.try_lock() {
+          Ok(guard) => SessionInfo {
+            cookie_cached: guard.cookie.is_some(),
+            logged_in_at: guard.logged_in_at.and_then(unix_secs),
+            last_used_at: guard
+              .last_used
+              .and_then(|t| SystemTime::now().checked_sub(t.elapsed()))
+              .and_then(unix_secs),
+            last_login_error: guard.last_login.clone().and_then(|v| v.err()),
+            ..Default::default()
+          },
+          Err(_) => SessionInfo {
+            login_in_progress: true,
+            ..Default::default()
+          },
+        };
+        (id.clone(), info)
+      })
+      .collect()
+  }
+
+  /// Drops sessions of servers that were removed or changed in any way but
+  /// `name` and `enabled`, and all of them when `minTlsVersion` changed;
+  /// sessions of untouched servers stay valid.
+  async fn clear_changed(&self, old: &Catalog, new: &Catalog) {
+    let tls_changed = old.settings.min_tls_version != new.settings.min_tls_version;
+    let login_cfg = |entry: &ServerEntry| ServerConfig {
+      name: String::new(),
+      enabled: true,
+      ..entry.cfg.clone()
+    };
+    let mut map = self.sessions.lock().await;
+    map.retain(|id, _| {
+      let (Some(old), Some(new)) = (old.servers.get(id), new.servers.get(id)) else {
+        return false;
+      };
+      !tls_changed
+        && login_cfg(old) == login_cfg(new)
+        && old.proxy == new.proxy
+        && old.user_agent == new.user_agent
+    });
+  }
+
+  async fn ensure_cookie(&self, entry: &ServerEntry, force: bool) -> Result<String> {
+    if !entry.cfg.needs_qbit_login() {
+      return Err(anyhow!("qBittorrent server is configured without login"));
+    }
+    if entry.cfg.username.is_empty() && entry.cfg.password.is_empty() {
+      return Err(anyhow!(
+        "qBittorrent server requires username/password in config"
+      ));
+    }
+
+    // Single flight: concurrent refreshes queue on the slot lock and reuse
+    // the result of the login that finished while they waited.
+    let slot = self.session(&entry.cfg.id).await;
+    let seen = slot.logins.load(Ordering::Acquire);
+    let mut guard = slot.session.lock().await;
+
+    guard.last_used = Some(Instant::now());
+    if slot.logins.load(Ordering::Acquire) != seen {
+      if let Some(outcome) = guard.last_login.clone() {
+        return outcome.map_err(|err| anyhow!(err));
+      }
+    }
+    if let Some(cookie) = guard.cookie.clone() {
+      if !force {
+        return Ok(cookie);
+      }
+      self.metrics.record_reauth(&entry.cfg.id, "relogin");
+    }
+
+    let attempts = entry.login_attempts.max(1);
+    let mut attempt = 1;
+    let cookies = loop {
+      match self.login(entry).await {
+        Ok(v) => break v,
+        Err(LoginError::Transient(err)) if attempt < attempts => {
+          let backoff = LOGIN_RETRY_BACKOFF * 2u32.saturating_pow(attempt - 1);
+          tracing::debug!(
+            server = %entry.cfg.id,
+            attempt,
+            attempts,
+            error = %err,
+            "qB login attempt failed, retrying"
+          );
+          tokio::time::sleep(backoff.min(LOGIN_RETRY_BACKOFF_MAX)).await;
+          attempt += 1;
+        }
+        Err(LoginError::Transient(err)) | Err(LoginError::Rejected(err)) => {
+          tracing::debug!(server = %entry.cfg.id, attempt, attempts, error = %err, "qB login failed");
+          guard.last_login = Some(Err(format!("{err:#}")));
+          slot.logins.fetch_add(1, Ordering::Release);
+          return Err(err);
+        }
+      }
+    };
+
+    let cookie = cookies.join("; ");
+    guard.cookie = Some(cookie.clone());
+    guard.logged_in_at = Some(SystemTime::now());
+    guard.api_version = self.fetch_api_version(entry, Some(&cookie), QBIT_LOGIN_TIMEOUT).await;
+    guard.last_login = Some(Ok(cookie.clone()));
+    slot.logins.fetch_add(1, Ordering::Release);
+    Ok(cookie)
+  }
+
+  /// Cached Web API version. Servers without login are probed on first use;
+  /// returns `None` rather than waiting while a login is in progress.
+  async fn api_version(&self, entry: &ServerEntry, timeout: Duration) -> Option<String> {
+    let slot = self.session(&entry.cfg.id).await;
+    let mut guard = slot.session.try_lock().ok()?;
+    if guard.api_version.is_none() && !entry.cfg.needs_qbit_login() {
+      guard.api_version = self.fetch_api_version(entry, None, timeout).await;
+    }
+    guard.api_version.clone()
+  }
+
+  async fn fetch_api_version(
+    &self,
+    entry: &ServerEntry,
+    cookie: Option<&str>,
+    timeout: Duration,
+  ) -> Option<String> {
+    let url = join_url(&entry.base, "/api/v2/app/webapiVersion").ok()?;
+    let client = entry.client.as_ref().unwrap_or(&self.client);
+    let mut req = client
+      .get(url)
+      .timeout(timeout)
+      .header("Origin", &entry.origin)
+      .header("Referer", format!("{}/", entry.origin));
+    if let Some(cookie) = cookie {
+      req = req.header(header::COOKIE, cookie);
+    }
+    if let Some(v) = &entry.host {
+      req = req.header(header::HOST, v.clone());
+    }
+    let resp = req.send().await.ok()?;
+    if resp.status() != StatusCode::OK {
+      return None;
+    }
+    let body = resp.text().await.ok()?;
+    let version = body.trim();
+    let valid = !version.is_empty()
+      && version.len() <= 16
+      && version.chars().all(|c| c.is_ascii_digit() || c == '.');
+    valid.then(|| version.to_string())
+  }
+
+  /// Logs out and forgets sessions no request has used for `idle`. Busy
+  /// slots are skipped until the next sweep.
+  async fn expire_idle(&self, catalog: &RwLock<Catalog>, idle: Duration) {
+    let slots = {
+      let map = self.sessions.lock().await;
+      map.iter().map(|(id, slot)| (id.clone(), slot.clone())).collect::<Vec<_>>()
+    };
+    for (id, slot) in slots {
+      let cookie = {
+        let Ok(mut guard) = slot.session.try_lock() else {
+          continue;
+        };
+        if guard.last_used.is_none_or(|t| t.elapsed() < idle) {
+          continue;
+        }
+        guard.last_login = None;
+        guard.logged_in_at = None;
+        guard.cookie.take()
+      };
+      let Some(cookie) = cookie else {
+        continue;
+      };
+      let Some(entry) = catalog.read().await.servers.get(&id).cloned() else {
+        continue;
+      };
+      tracing::info!(server = %id, "qB session idle, logging out");
+      if let Err(err) = self.logout(&entry, &cookie).await {
+        tracing::debug!(server = %id, error = %format_args!("{err:#}"), "qB logout failed");
+      }
+    }
+  }
+
+  async fn logout(&self, entry: &ServerEntry, cookie: &str) -> Result<()> {
+    let url = join_url(&entry.base, "/api/v2/auth/logout")?;
+    let client = entry.client.as_ref().unwrap_or(&self.client);
+    let mut req = client
+      .post(url)
+      .timeout(QBIT_LOGIN_TIMEOUT)
+      .header("Origin", &entry.origin)
+      .header("Referer", format!("{}/", entry.origin))
+      .header(header::COOKIE, cookie);
+    if let Some(v) = &entry.host {
+      req = req.header(header::HOST, v.clone());
+    }
+    let resp = req.send().await.context("qB logout request failed")?;
+    if !resp.status().is_success() {
+      return Err(anyhow!("qB logout failed: status={}", resp.status()));
+    }
+    Ok(())
+  }
+
+  async fn send_login(
+    &self,
+    entry: &ServerEntry,
+    origin: &str,
+  ) -> std::result::Result<reqwest::Response, LoginError> {
+    let login_url = join_url(&entry.base, "/api/v2/auth/login").map_err(LoginError::Rejected)?;
+
+    let client = entry.client.as_ref().unwrap_or(&self.client);
+    let mut builder = client
+      .post(login_url)
+      .timeout(QBIT_LOGIN_TIMEOUT)
+      .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+      .header("Origin", origin)
+      .header("Referer", format!("{origin}/"));
+    if let Some(v) = &entry.user_agent {
+      builder = builder.header(header::USER_AGENT, v.clone());
+    }
+    if let Some(v) = &entry.host {
+      builder = builder.header(header::HOST, v.clone());
+    }
+    let resp = builder
+      .form(&[
+        ("username", entry.cfg.username.clone()),
+        ("password", entry.cfg.password.clone()),
+      ])
+      .send()
+      .await;
+    match resp {
+      Ok(v) => Ok(v),
+      Err(err) => Err(LoginError::Transient(
+        upstream_error(entry, err, "qB login request failed").await,
+      )),
+    }
+  }
+
+  /// Performs one login round-trip. Network errors and 5xx responses are
+  /// transient; anything the backend answered deliberately is a rejection.
+  async fn login(&self, entry: &ServerEntry) -> std::result::Result<Vec<String>, LoginError> {
+    // Wrong credentials get `200 Fails.`, so a rejection here is the
+    // cross-site check.
+    let (mut resp, rejected) = csrf_rejection(self.send_login(entry, &entry.origin).await?).await;
+    let origin = csrf_retry_origin(entry, None);
+    if rejected && origin != entry.origin {
+      tracing::info!(server = %entry.cfg.id, %origin, "qB rejected login as cross-site, retrying");
+      resp = self.send_login(entry, &origin).await?;
+    }
+
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    let body = resp
+      .bytes()
+      .await
+      .unwrap_or_else(|_| Bytes::from_static(b""));
+
+    if status != StatusCode::OK {
+      let text = String::from_utf8_lossy(&body).trim().to_string();
+      let err = anyhow!("qB login failed: status={} body={:?}", status, text);
+      if status.is_server_error() {
+        return Err(LoginError::Transient(err));
+      }
+      return Err(LoginError::Rejected(err));
+    }
+    if !String::from_utf8_lossy(&body).contains("Ok") {
+      let text = String::from_utf8_lossy(&body).trim().to_string();
+      return Err(LoginError::Rejected(anyhow!("qB login failed: body={:?}", text)));
+    }
+
+    let cookies = extract_set_cookie_pairs(&headers);
+    tracing::debug!(
+      server = %entry.cfg.id,
+      set_cookie_headers = cookies.seen,
+      skipped = cookies.skipped.len(),
+      "qB login response cookies"
+    );
+    if cookies.pairs.is_empty() {
+      return Err(LoginError::Rejected(anyhow!(
+        "qB login did not set cookies ({})",
+        cookies.describe_empty()
+      )));
+    }
+    Ok(cookies.pairs)
+  }
+}
+
+enum LoginError {
+  Transient(anyhow::Error),
+  Rejected(anyhow::Error),
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerPublic {
+  id: String,
+  name: String,
+  #[serde(rename = "type")]
+  kind: BackendType,
+  base_url: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  latency_ms: Option<u64>,
+  reachable: bool,
+  enabled: bool,
+  /// qBittorrent Web API version, once known.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  version: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  color: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  icon: Option<String>,
+  /// `"mdns"` for discovered servers; absent for configured ones.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  source: Option<&'static str>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StatusResponse {
+  schema: u32,
+  selected_id: String,
+  default_id: String,
+  type_defaults: TypeDefaults,
+  maintenance: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  maintenance_message: Option<String>,
+  servers: Vec<ServerPublic>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct TypeDefaults {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  qbit: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  trans: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionResponse {
+  schema: u32,
+  version: &'static str,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  commit: Option<&'static str>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ProbeRequest {
+  method: String,
+  path: String,
+  #[serde(default)]
+  headers: HashMap<String, String>,
+  #[serde(default)]
+  body: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProbeResponse {
+  status: u16,
+  headers: HashMap<String, String>,
+  body: String,
+  truncated: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TestAllResponse {
+  schema: u32,
+  servers: Vec<ServerTestResult>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerTestResult {
+  id: String,
+  ok: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  latency_ms: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct SessionsResponse {
+  schema: u32,
+  servers: Vec<SessionInfo>,
+}
+
+/// Login state of one qBittorrent server; timestamps are Unix seconds.
+#[derive(Debug, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionInfo {
+  id: String,
+  cookie_cached: bool,
+  #[serde(skip_serializing_if = "is_false")]
+  login_in_progress: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  logged_in_at: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  last_used_at: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  last_login_error: Option<String>,
+}
+
+fn unix_secs(t: SystemTime) -> Option<u64> {
+  t.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RouteResponse {
+  schema: u32,
+  path: String,
+  server_id: String,
+  #[serde(rename = "type")]
+  kind: BackendType,
+  /// The server's type does not match the route and `routeMismatch` is
+  /// `reject`, so the gateway would answer `404` itself.
+  #[serde(skip_serializing_if = "is_false")]
+  route_mismatch: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ReachabilityResponse {
+  schema: u32,
+  servers: BTreeMap<String, Reachability>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Reachability {
+  reachable: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  latency_ms: Option<u64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct HealthResponse {
+  schema: u32,
+  /// `ok` when every monitored server is up, `degraded` otherwise.
+  status: &'static str,
+  servers: HealthCounts,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct HealthCounts {
+  up: usize,
+  down: usize,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionsResponse {
+  schema: u32,
+  servers: Vec<ServerVersions>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerVersions {
+  id: String,
+  #[serde(rename = "type")]
+  kind: BackendType,
+  #[serde(flatten)]
+  versions: AppVersions,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AppVersions {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  app_version: Option<String>,
+  /// qBittorrent only.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  libtorrent_version: Option<String>,
+  /// Transmission only.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  rpc_version: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct MaintenanceRequest {
+  enabled: bool,
+  #[serde(default)]
+  message: Option<String>,
+  #[serde(default)]
+  persist: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RenameRequest {
+  new_id: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct SelectRequest {
+  id: String,
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -300,292 +2659,2989 @@ struct ConfigServerPublic {
   #[serde(rename = "type")]
   kind: BackendType,
   base_url: String,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  command: Vec<String>,
   username: String,
   has_password: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  auth: Option<QbitAuth>,
+  enabled: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  client_cert_path: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  client_key_path: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  ca_cert_path: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  http2: Option<Http2Mode>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  allowed_paths: Vec<String>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  denied_paths: Vec<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  path_match: Option<PathMatch>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  response_cache: Vec<ResponseCacheRule>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  destructive_paths: Vec<String>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  destructive_rpc_methods: Vec<String>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  rewrite: Vec<RewriteRule>,
+  #[serde(skip_serializing_if = "is_false")]
+  passthrough_ui: bool,
+  #[serde(skip_serializing_if = "is_false")]
+  forward_client_ip: bool,
+  #[serde(skip_serializing_if = "is_false")]
+  validate_rpc: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  user_agent: Option<String>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  strip_request_headers: Vec<String>,
+  /// Values are masked like passwords.
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  query_params: BTreeMap<String, String>,
+  #[serde(skip_serializing_if = "is_false")]
+  force_query_params: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  upstream_host: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  max_concurrent_requests: Option<usize>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  max_upload_bytes: Option<u64>,
+  /// Credentials are stripped.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  upstream_proxy: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  color: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  icon: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfigResponse {
+  schema: u32,
+  default_server_id: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  default_qbit_id: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  default_trans_id: Option<String>,
+  servers: Vec<ConfigServerPublic>,
+  /// `false` when saving would fail (read-only mount, layered config), so the
+  /// UI can disable editing.
+  config_writable: bool,
+  /// `false` when `configEditable` disabled the mutating config routes.
+  config_editable: bool,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfigUpdateRequest {
+  #[serde(default)]
+  default_server_id: String,
+  #[serde(default)]
+  default_qbit_id: Option<String>,
+  #[serde(default)]
+  default_trans_id: Option<String>,
+  servers: Vec<ConfigUpdateServer>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConfigUpdateServer {
+  id: String,
+  #[serde(default)]
+  name: String,
+  #[serde(rename = "type")]
+  kind: BackendType,
+  #[serde(default)]
+  base_url: String,
+  #[serde(default)]
+  command: Option<Vec<String>>,
+  #[serde(default)]
+  username: String,
+  password: Option<String>,
+  #[serde(default)]
+  auth: Option<QbitAuth>,
+  #[serde(default)]
+  enabled: Option<bool>,
+  #[serde(default)]
+  client_cert_path: Option<String>,
+  #[serde(default)]
+  client_key_path: Option<String>,
+  #[serde(default)]
+  ca_cert_path: Option<String>,
+  /// `"alpn"` / `"prior-knowledge"`; an empty string switches back to HTTP/1.1.
+  #[serde(default)]
+  http2: Option<String>,
+  #[serde(default)]
+  allowed_paths: Option<Vec<String>>,
+  #[serde(default)]
+  denied_paths: Option<Vec<String>>,
+  #[serde(default)]
+  path_match: Option<PathMatch>,
+  #[serde(default)]
+  response_cache: Option<Vec<ResponseCacheRule>>,
+  #[serde(default)]
+  destructive_paths: Option<Vec<String>>,
+  #[serde(default)]
+  destructive_rpc_methods: Option<Vec<String>>,
+  #[serde(default)]
+  rewrite: Option<Vec<RewriteRule>>,
+  #[serde(default)]
+  passthrough_ui: Option<bool>,
+  #[serde(default)]
+  forward_client_ip: Option<bool>,
+  #[serde(default)]
+  validate_rpc: Option<bool>,
+  #[serde(default)]
+  user_agent: Option<String>,
+  #[serde(default)]
+  strip_request_headers: Option<Vec<String>>,
+  #[serde(default)]
+  query_params: Option<BTreeMap<String, String>>,
+  #[serde(default)]
+  force_query_params: Option<bool>,
+  #[serde(default)]
+  upstream_host: Option<String>,
+  #[serde(default)]
+  max_concurrent_requests: Option<usize>,
+  #[serde(default)]
+  max_upload_bytes: Option<u64>,
+  #[serde(default)]
+  upstream_proxy: Option<String>,
+  #[serde(default)]
+  color: Option<String>,
+  #[serde(default)]
+  icon: Option<String>,
+}
+
+pub async fn serve_from_env() -> Result<()> {
+  let listen = env_or_default("LISTEN_ADDR", ":8080");
+  let static_dir = env_or_default("STATIC_DIR", "./dist");
+  let config_path = env_or_default("STANDALONE_CONFIG", "/config/standalone.json");
+
+  serve(&listen, PathBuf::from(static_dir), PathBuf::from(config_path)).await
+}
+
+/// Reads `key` from the environment, using `default` when unset or blank.
+pub fn env_or_default(key: &str, default: &str) -> String {
+  let Ok(v) = std::env::var(key) else {
+    return default.to_string();
+  };
+  let v = v.trim();
+  if v.is_empty() {
+    return default.to_string();
+  }
+  v.to_string()
+}
+
+pub async fn serve(listen: &str, static_dir: PathBuf, config_path: PathBuf) -> Result<()> {
+  let addr = normalize_listen_addr(listen)?;
+  let shutdown = CancellationToken::new();
+  let (app, settings) = build_app(static_dir, config_path, shutdown.clone())?;
+
+  let listener = bind_listener(addr, &settings).await?;
+  tune_listener(&listener, &settings)?;
+
+  let tls = load_tls(&settings).await?;
+
+  tracing::info!(listen = %addr, tls = tls.is_some(), "standalone-service listening");
+  run_server(listener, app, &settings, tls, shutdown).await
+}
+
+pub async fn spawn_with_listener(
+  listener: tokio::net::TcpListener,
+  static_dir: PathBuf,
+  config_path: PathBuf,
+) -> Result<SocketAddr> {
+  let handle = spawn_gateway(listener, static_dir, config_path).await?;
+  Ok(handle.addr())
+}
+
+/// A gateway running in the background, started by [`spawn_gateway`] or
+/// [`spawn_with_config`]. Dropping the handle leaves the gateway running.
+pub struct GatewayHandle {
+  addr: SocketAddr,
+  shutdown: CancellationToken,
+  task: tokio::task::JoinHandle<()>,
+  /// Config file written by [`spawn_with_config`], removed on shutdown.
+  temp_config: Option<PathBuf>,
+}
+
+impl GatewayHandle {
+  pub fn addr(&self) -> SocketAddr {
+    self.addr
+  }
+
+  /// Stops accepting connections, ends status streams and background tasks,
+  /// and waits for in-flight requests to finish.
+  pub async fn shutdown(self) -> Result<()> {
+    self.shutdown.cancel();
+    let result = self.task.await.context("gateway task failed");
+    if let Some(path) = &self.temp_config {
+      let _ = tokio::fs::remove_file(path).await;
+    }
+    result
+  }
+}
+
+/// Like [`spawn_with_listener`], but returns a handle that can stop the
+/// gateway again.
+pub async fn spawn_gateway(
+  listener: tokio::net::TcpListener,
+  static_dir: PathBuf,
+  config_path: PathBuf,
+) -> Result<GatewayHandle> {
+  let addr = listener.local_addr().context("listener local_addr")?;
+  let shutdown = CancellationToken::new();
+  let (app, settings) = build_app(static_dir, config_path, shutdown.clone())?;
+  tune_listener(&listener, &settings)?;
+  let tls = load_tls(&settings).await?;
+
+  let signal = shutdown.clone();
+  let task = tokio::spawn(async move {
+    if let Err(err) = run_server(listener, app, &settings, tls, signal).await {
+      tracing::error!(error = %err, "http server error");
+    }
+  });
+
+  Ok(GatewayHandle {
+    addr,
+    shutdown,
+    task,
+    temp_config: None,
+  })
+}
+
+/// Starts a gateway on an ephemeral loopback port with `config` (the JSON of a
+/// `standalone.json`) written to a temporary file, e.g. for integration tests.
+/// Config edits made through the API go to that file.
+pub async fn spawn_with_config(config: &str, static_dir: PathBuf) -> Result<GatewayHandle> {
+  static NEXT: AtomicU64 = AtomicU64::new(0);
+  let name = format!(
+    "torrentmix-gateway-{}-{}.json",
+    std::process::id(),
+    NEXT.fetch_add(1, Ordering::Relaxed)
+  );
+  let path = std::env::temp_dir().join(name);
+  tokio::fs::write(&path, config)
+    .await
+    .with_context(|| format!("write config: {}", path.display()))?;
+
+  let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+    .await
+    .context("bind gateway listener")?;
+  match spawn_gateway(listener, static_dir, path.clone()).await {
+    Ok(handle) => Ok(GatewayHandle {
+      temp_config: Some(path),
+      ..handle
+    }),
+    Err(err) => {
+      let _ = tokio::fs::remove_file(&path).await;
+      Err(err)
+    }
+  }
+}
+
+/// Loads the certificate pair named by `tlsCertPath` / `tlsKeyPath`, if any.
+async fn load_tls(settings: &GatewaySettings) -> Result<Option<RustlsConfig>> {
+  let (Some(cert), Some(key)) = (&settings.tls_cert_path, &settings.tls_key_path) else {
+    return Ok(None);
+  };
+  let config = RustlsConfig::from_pem_file(cert, key).await.with_context(|| {
+    format!("load TLS certificate {} / key {}", cert.display(), key.display())
+  })?;
+  Ok(Some(config))
+}
+
+/// Serves `app` on `listener`, terminating TLS when `tls` is set, until
+/// `shutdown` is cancelled and open requests have finished.
+async fn run_server(
+  listener: tokio::net::TcpListener,
+  app: Router,
+  settings: &GatewaySettings,
+  tls: Option<RustlsConfig>,
+  shutdown: CancellationToken,
+) -> Result<()> {
+  let nodelay = settings.tcp_nodelay.unwrap_or(false);
+  let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+  let Some(tls) = tls else {
+    return axum::serve(listener, make_service)
+      .tcp_nodelay(nodelay)
+      .with_graceful_shutdown(shutdown.cancelled_owned())
+      .await
+      .context("http server error");
+  };
+
+  if let Some(addr) = &settings.http_redirect_addr {
+    let https_port = listener.local_addr().context("listener local_addr")?.port();
+    spawn_http_redirect(addr, settings, https_port, shutdown.clone()).await?;
+  }
+  let handle = axum_server::Handle::new();
+  let graceful = handle.clone();
+  tokio::spawn(async move {
+    shutdown.cancelled().await;
+    graceful.graceful_shutdown(None);
+  });
+  let listener = listener.into_std().context("listener into_std")?;
+  let acceptor = RustlsAcceptor::new(tls);
+  let server = axum_server::from_tcp(listener).handle(handle);
+  let result = if nodelay {
+    server
+      .acceptor(acceptor.acceptor(axum_server::accept::NoDelayAcceptor::new()))
+      .serve(make_service)
+      .await
+  } else {
+    server.acceptor(acceptor).serve(make_service).await
+  };
+  result.context("https server error")
+}
+
+#[derive(Clone)]
+struct RedirectTarget {
+  origin: Option<String>,
+  https_port: u16,
+}
+
+/// Binds `httpRedirectAddr` and serves `301` redirects to HTTPS on it until
+/// `shutdown` is cancelled.
+async fn spawn_http_redirect(
+  addr: &str,
+  settings: &GatewaySettings,
+  https_port: u16,
+  shutdown: CancellationToken,
+) -> Result<()> {
+  let addr = normalize_listen_addr(addr).context("httpRedirectAddr")?;
+  let listener = tokio::net::TcpListener::bind(addr)
+    .await
+    .with_context(|| format!("bind httpRedirectAddr {addr}"))?;
+  let target = RedirectTarget {
+    origin: settings.https_origin_url()?,
+    https_port,
+  };
+  let app = Router::new().fallback(redirect_to_https).with_state(target);
+  tracing::info!(listen = %addr, "redirecting plain HTTP to HTTPS");
+  tokio::spawn(async move {
+    let served = axum::serve(listener, app)
+      .with_graceful_shutdown(shutdown.cancelled_owned())
+      .await;
+    if let Err(err) = served {
+      tracing::error!(error = %err, "http redirect server error");
+    }
+  });
+  Ok(())
+}
+
+async fn redirect_to_https(
+  State(target): State<RedirectTarget>,
+  headers: HeaderMap,
+  uri: Uri,
+) -> Response {
+  let path = uri.path_and_query().map_or("/", |v| v.as_str());
+  let origin = match target.origin {
+    Some(origin) => origin,
+    None => {
+      let Some(host) = headers.get(header::HOST).and_then(|v| v.to_str().ok()) else {
+        return (StatusCode::BAD_REQUEST, "missing Host header").into_response();
+      };
+      let Ok(authority) = host.parse::<axum::http::uri::Authority>() else {
+        return (StatusCode::BAD_REQUEST, "invalid Host header").into_response();
+      };
+      match target.https_port {
+        443 => format!("https://{}", authority.host()),
+        port => format!("https://{}:{port}", authority.host()),
+      }
+    }
+  };
+  let Ok(location) = HeaderValue::from_str(&format!("{origin}{path}")) else {
+    return (StatusCode::BAD_REQUEST, "invalid request target").into_response();
+  };
+  (StatusCode::MOVED_PERMANENTLY, [(header::LOCATION, location)]).into_response()
+}
+
+/// Listed by `/` in headless mode; keep in sync with the router.
+const HEADLESS_ENDPOINTS: &[(&str, &str)] = &[
+  ("GET", "/__standalone__/version"),
+  ("GET", "/__standalone__/status"),
+  ("GET", "/__standalone__/status/stream"),
+  ("GET", "/__standalone__/health"),
+  ("GET", "/__standalone__/reachability"),
+  ("GET", "/__standalone__/route"),
+  ("GET", "/__standalone__/metrics"),
+  ("GET", "/__standalone__/sessions"),
+  ("POST", "/__standalone__/select"),
+  ("POST", "/__standalone__/select-and-status"),
+  ("POST", "/__standalone__/maintenance"),
+  ("GET", "/__standalone__/config"),
+  ("POST", "/__standalone__/config"),
+  ("GET", "/__standalone__/config/schema"),
+  ("GET", "/__standalone__/config/export"),
+  ("POST", "/__standalone__/config/import"),
+  ("POST", "/__standalone__/config/test-all"),
+  ("PATCH", "/__standalone__/config/servers/:id"),
+  ("GET", "/__standalone__/aggregate/versions"),
+  ("POST", "/__standalone__/servers/:id/probe"),
+  ("GET", "/__standalone__/servers/:id/favicon"),
+  ("POST", "/__standalone__/servers/:id/rename"),
+  ("ANY", "/api/*path"),
+  ("ANY", "/transmission/*path"),
+];
+
+fn build_app(
+  static_dir: PathBuf,
+  config_path: PathBuf,
+  shutdown: CancellationToken,
+) -> Result<(Router, GatewaySettings)> {
+  let config_path = Arc::new(config_path);
+
+  let catalog = Catalog::load(&config_path)?;
+  let settings = catalog.settings.clone();
+  let catalog = Arc::new(RwLock::new(catalog));
+
+  let upstream_proxy = settings.upstream_proxy();
+  let min_tls = settings.min_tls_version();
+  let metrics = Arc::new(Metrics::default());
+  let qbit = Arc::new(QbitSessions::new(upstream_proxy.as_ref(), min_tls, metrics.clone())?);
+  let client = with_min_tls(reqwest::Client::builder(), min_tls);
+  let client = with_upstream_proxy(client, upstream_proxy.as_ref())?
+    .timeout(PROXY_TIMEOUT)
+    .redirect(Policy::none())
+    .http1_only()
+    .build()
+    .context("build proxy http client")?;
+
+  let mut state = AppState {
+    catalog,
+    qbit,
+    client,
+    config_path,
+    index_path: Arc::new(static_dir.join("index.html")),
+    static_dir: Arc::new(static_dir),
+    maintenance: Arc::new(RwLock::new(settings.maintenance.clone().unwrap_or_default())),
+    health: Arc::new(RwLock::new(HashMap::new())),
+    favicons: Arc::new(Mutex::new(HashMap::new())),
+    probe_failures: Arc::new(Mutex::new(HashMap::new())),
+    trusted_proxies: parse_ip_nets("trustedProxies", &settings.trusted_proxies)?.into(),
+    responses: Arc::new(Mutex::new(HashMap::new())),
+    flights: Arc::new(std::sync::Mutex::new(HashMap::new())),
+    health_updates: None,
+    metrics,
+    discovered: Arc::new(Mutex::new(BTreeMap::new())),
+    detected_types: Arc::new(Mutex::new(HashMap::new())),
+    detect_failures: Arc::new(Mutex::new(HashMap::new())),
+    shutdown,
+  };
+
+  if let Some(idle) = settings.session_idle() {
+    state.spawn_background(run_session_sweeper(state.clone(), idle));
+  }
+
+  if !settings.mdns_discovery.is_empty() {
+    #[cfg(feature = "mdns")]
+    state.spawn_background(run_mdns_discovery(state.clone(), settings.mdns_discovery.clone()));
+    #[cfg(not(feature = "mdns"))]
+    tracing::warn!("mdnsDiscovery is set but this build has no mDNS support");
+  }
+
+  if let Some(interval) = settings.health_interval() {
+    state.health_updates = Some(Arc::new(watch::channel(0).0));
+    state.spawn_background(run_health_monitor(state.clone(), interval));
+  }
+
+  let detect_state = state.clone();
+  let prewarm = settings.prewarm.unwrap_or(false);
+  state.spawn_background(async move {
+    resolve_auto_servers(&detect_state, None).await;
+    if prewarm {
+      prewarm_servers(&detect_state).await;
+    }
+  });
+
+  let headless = settings.headless();
+  if headless {
+    tracing::info!("headless mode, static frontend disabled");
+  } else if !state.index_path.is_file() {
+    // Requests still get the fallback page; this surfaces the mistake early.
+    tracing::warn!(
+      static_dir = %state.static_dir.display(),
+      "index.html not found in STATIC_DIR: run `pnpm build` to generate dist/, point \
+       STATIC_DIR at it, or enable headless mode"
+    );
+  }
+  let index = if headless {
+    get(handle_headless_index)
+  } else {
+    get(handle_index)
+  };
+  let app = Router::new()
+    .route("/", index.clone())
+    .route("/index.html", index)
+    .route("/__standalone__/version", get(handle_version))
+    .route("/__standalone__/status", get(handle_status))
+    .route("/__standalone__/status/stream", get(handle_status_stream))
+    .route("/__standalone__/health", get(handle_health))
+    .route("/__standalone__/reachability", get(handle_reachability))
+    .route("/__standalone__/route", get(handle_route))
+    .route("/__standalone__/metrics", get(handle_metrics))
+    .route("/__standalone__/sessions", get(handle_sessions))
+    .route("/__standalone__/select", post(handle_select))
+    .route("/__standalone__/select-and-status", post(handle_select_and_status))
+    .route("/__standalone__/maintenance", post(handle_maintenance))
+    .route("/__standalone__/config", get(handle_config_get).post(handle_config_update))
+    .route("/__standalone__/config/schema", get(handle_config_schema))
+    .route("/__standalone__/config/export", get(handle_config_export))
+    .route("/__standalone__/config/import", post(handle_config_import))
+    .route("/__standalone__/config/test-all", post(handle_config_test_all))
+    .route("/__standalone__/config/servers/:id", patch(handle_config_server_patch))
+    .route("/__standalone__/aggregate/versions", get(handle_aggregate_versions))
+    .route("/__standalone__/servers/:id/probe", post(handle_server_probe))
+    .route("/__standalone__/servers/:id/favicon", get(handle_server_favicon))
+    .route("/__standalone__/servers/:id/rename", post(handle_server_rename))
+    .route("/api/*path", any(handle_proxy))
+    .route("/transmission/*path", any(handle_proxy));
+  let app = settings
+    .proxy_prefixes()
+    .iter()
+    .fold(app, |app, prefix| app.route(&format!("{prefix}/*path"), any(handle_proxy)));
+  let app = if headless {
+    app.fallback(handle_headless_fallback)
+  } else {
+    app.fallback(handle_fallback)
+  };
+  let app = app
+    .with_state(state)
+    .layer(CatchPanicLayer::custom(panic_response))
+    .layer(middleware::from_fn(log_panics));
+
+  let app = match settings.max_connections {
+    Some(limit) => {
+      let shed = ConnectionLimit {
+        permits: Arc::new(Semaphore::new(limit)),
+        retry_after: RetryAfter::new(
+          settings.retry_after_secs.unwrap_or(1),
+          settings.retry_after_jitter_secs.unwrap_or(0),
+        ),
+      };
+      app.layer(middleware::from_fn_with_state(Arc::new(shed), limit_connections))
+    }
+    None => app,
+  };
+  let app = match ForwardAuthGate::from_settings(&settings)? {
+    Some(gate) => app.layer(middleware::from_fn_with_state(Arc::new(gate), check_forward_auth)),
+    None => app,
+  };
+  // Outside the connection limit so refused clients don't take permits.
+  let app = match IpFilter::from_settings(&settings)? {
+    Some(filter) => app.layer(middleware::from_fn_with_state(Arc::new(filter), filter_client_ips)),
+    None => app,
+  };
+
+  Ok((app, settings))
+}
+
+/// Parsed `forwardAuth`.
+struct ForwardAuthGate {
+  url: Url,
+  client: reqwest::Client,
+  management: bool,
+  proxy: bool,
+  proxy_prefixes: Vec<String>,
+  copy_headers: Vec<HeaderName>,
+  scheme: &'static str,
+}
+
+impl ForwardAuthGate {
+  fn from_settings(settings: &GatewaySettings) -> Result<Option<Self>> {
+    let Some(cfg) = &settings.forward_auth else {
+      return Ok(None);
+    };
+    let url = Url::parse(cfg.url.trim()).context("forwardAuth.url")?;
+    if !matches!(url.scheme(), "http" | "https") {
+      return Err(anyhow!("forwardAuth.url must be an http(s) URL"));
+    }
+    if cfg.timeout_ms == Some(0) {
+      return Err(anyhow!("forwardAuth.timeoutMs must be greater than 0"));
+    }
+    let copy_headers = match &cfg.copy_headers {
+      Some(names) => names
+        .iter()
+        .map(|name| {
+          HeaderName::from_bytes(name.trim().as_bytes())
+            .with_context(|| format!("forwardAuth.copyHeaders: invalid header name {:?}", name))
+        })
+        .collect::<Result<Vec<_>>>()?,
+      None => DEFAULT_FORWARD_AUTH_HEADERS
+        .iter()
+        .map(|name| HeaderName::from_static(name))
+        .collect(),
+    };
+    let client = with_min_tls(reqwest::Client::builder(), settings.min_tls_version())
+      .redirect(Policy::none())
+      .timeout(cfg.timeout_ms.map_or(FORWARD_AUTH_TIMEOUT, Duration::from_millis))
+      .build()
+      .context("build forwardAuth client")?;
+    Ok(Some(Self {
+      url,
+      client,
+      management: cfg.management.unwrap_or(true),
+      proxy: cfg.proxy.unwrap_or(true),
+      proxy_prefixes: settings.proxy_prefixes(),
+      copy_headers,
+      scheme: if settings.tls_cert_path.is_some() { "https" } else { "http" },
+    }))
+  }
+
+  fn protects(&self, path: &str) -> bool {
+    if matches!(path, "/__standalone__/version" | "/__standalone__/health") {
+      return false;
+    }
+    if is_proxied_path(path, &self.proxy_prefixes) {
+      self.proxy
+    } else {
+      self.management
+    }
+  }
+}
+
+/// Whether `path` is served by [`handle_proxy`].
+fn is_proxied_path(path: &str, proxy_prefixes: &[String]) -> bool {
+  path.starts_with("/api/")
+    || path.starts_with("/transmission/")
+    || proxy_prefixes
+      .iter()
+      .any(|prefix| path.strip_prefix(prefix.as_str()).is_some_and(|v| v.starts_with('/')))
+}
+
+/// Runs the `forwardAuth` check for protected routes. Denials (login
+/// redirects, `401` challenges) are relayed to the client as answered.
+async fn check_forward_auth(
+  State(gate): State<Arc<ForwardAuthGate>>,
+  mut req: Request<Body>,
+  next: Next,
+) -> Response {
+  for name in &gate.copy_headers {
+    req.headers_mut().remove(name);
+  }
+  if !gate.protects(req.uri().path()) {
+    return next.run(req).await;
+  }
+
+  let uri = req.uri().path_and_query().map_or("/", |v| v.as_str());
+  let mut check = gate
+    .client
+    .get(gate.url.clone())
+    .header("x-forwarded-method", req.method().as_str())
+    .header("x-forwarded-proto", gate.scheme)
+    .header("x-forwarded-uri", uri);
+  if let Some(host) = req.headers().get(header::HOST) {
+    check = check.header("x-forwarded-host", host.clone());
+  }
+  if let Some(info) = req.extensions().get::<ConnectInfo<SocketAddr>>() {
+    check = check.header("x-forwarded-for", info.0.ip().to_canonical().to_string());
+  }
+  for name in [header::COOKIE, header::AUTHORIZATION] {
+    for value in req.headers().get_all(&name) {
+      check = check.header(&name, value.clone());
+    }
+  }
+
+  let resp = match check.send().await {
+    Ok(v) => v,
+    Err(err) => {
+      tracing::warn!(error = %err, "forward auth request failed");
+      return (StatusCode::BAD_GATEWAY, "auth service unavailable").into_response();
+    }
+  };
+  if resp.status().is_success() {
+    for name in &gate.copy_headers {
+      for value in resp.headers().get_all(name) {
+        req.headers_mut().append(name.clone(), value.clone());
+      }
+    }
+    return next.run(req).await;
+  }
+
+  let status = resp.status();
+  let mut headers = HeaderMap::new();
+  for name in [
+    header::LOCATION,
+    header::SET_COOKIE,
+    header::WWW_AUTHENTICATE,
+    header::CONTENT_TYPE,
+  ] {
+    for value in resp.headers().get_all(&name) {
+      headers.append(name.clone(), value.clone());
+    }
+  }
+  let body = Body::from_stream(resp.bytes_stream().map_err(std::io::Error::other));
+  (status, headers, body).into_response()
+}
+
+/// Parsed `allowIps` / `denyIps` / `trustedProxies`.
+struct IpFilter {
+  allow: Vec<IpNet>,
+  deny: Vec<IpNet>,
+  trusted_proxies: Vec<IpNet>,
+}
+
+impl IpFilter {
+  /// `None` when neither list is set.
+  fn from_settings(settings: &GatewaySettings) -> Result<Option<Self>> {
+    let filter = Self {
+      allow: parse_ip_nets("allowIps", &settings.allow_ips)?,
+      deny: parse_ip_nets("denyIps", &settings.deny_ips)?,
+      trusted_proxies: parse_ip_nets("trustedProxies", &settings.trusted_proxies)?,
+    };
+    if filter.allow.is_empty() && filter.deny.is_empty() {
+      return Ok(None);
+    }
+    Ok(Some(filter))
+  }
+
+  fn allows(&self, ip: IpAddr) -> bool {
+    if self.deny.iter().any(|net| net.contains(&ip)) {
+      return false;
+    }
+    self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+  }
+
+  fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+    self.trusted_proxies.iter().any(|net| net.contains(&ip))
+  }
+
+  /// The peer address, or for a trusted proxy the nearest `X-Forwarded-For`
+  /// hop that is not itself a trusted proxy.
+  fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let mut client = peer;
+    if !self.is_trusted_proxy(client) {
+      return client;
+    }
+    let hops = headers
+      .get_all("x-forwarded-for")
+      .iter()
+      .filter_map(|v| v.to_str().ok())
+      .flat_map(|v| v.split(','))
+      .collect::<Vec<_>>();
+    for hop in hops.into_iter().rev() {
+      let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+        break;
+      };
+      client = ip.to_canonical();
+      if !self.is_trusted_proxy(client) {
+        break;
+      }
+    }
+    client
+  }
+}
+
+/// Entries are CIDR ranges or single addresses.
+fn parse_ip_nets(key: &str, values: &[String]) -> Result<Vec<IpNet>> {
+  values
+    .iter()
+    .map(|raw| {
+      let v = raw.trim();
+      v.parse::<IpNet>()
+        .or_else(|_| v.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| anyhow!("{key}: {:?} is not a CIDR range or IP address", raw))
+    })
+    .collect()
+}
+
+/// Attached to the `500` built by [`panic_response`] so that [`log_panics`]
+/// can report the panic along with the request.
+#[derive(Clone)]
+struct PanicMessage(String);
+
+fn panic_response(err: Box<dyn std::any::Any + Send + 'static>) -> Response {
+  let message = err
+    .downcast_ref::<String>()
+    .cloned()
+    .or_else(|| err.downcast_ref::<&str>().map(|v| v.to_string()))
+    .unwrap_or_else(|| "unknown panic".to_string());
+  let mut resp = (StatusCode::INTERNAL_SERVER_ERROR, "internal server error").into_response();
+  resp.extensions_mut().insert(PanicMessage(message));
+  resp
+}
+
+async fn log_panics(req: Request<Body>, next: Next) -> Response {
+  let method = req.method().clone();
+  let path = req.uri().path().to_string();
+  let mut resp = next.run(req).await;
+  if let Some(PanicMessage(message)) = resp.extensions_mut().remove::<PanicMessage>() {
+    tracing::error!(method = %method, path = %path, panic = %message, "handler panicked");
+  }
+  resp
+}
+
+async fn filter_client_ips(
+  State(filter): State<Arc<IpFilter>>,
+  req: Request<Body>,
+  next: Next,
+) -> Response {
+  let peer = req
+    .extensions()
+    .get::<ConnectInfo<SocketAddr>>()
+    .map(|info| info.0.ip().to_canonical());
+  let allowed = peer.is_some_and(|peer| filter.allows(filter.client_ip(peer, req.headers())));
+  if !allowed {
+    return (StatusCode::FORBIDDEN, "forbidden").into_response();
+  }
+  next.run(req).await
+}
+
+struct ConnectionLimit {
+  permits: Arc<Semaphore>,
+  retry_after: RetryAfter,
+}
+
+/// `Retry-After` values spread over `base..=base + jitter` seconds. The
+/// generator is seeded once per process.
+struct RetryAfter {
+  base: u64,
+  jitter: u64,
+  state: AtomicU64,
+}
+
+impl RetryAfter {
+  fn new(base: u64, jitter: u64) -> Self {
+    use std::hash::{BuildHasher, Hasher};
+    // std seeds `RandomState` with fresh random keys in every process.
+    let seed = std::collections::hash_map::RandomState::new().build_hasher().finish();
+    Self {
+      base,
+      jitter,
+      state: AtomicU64::new(seed),
+    }
+  }
+
+  fn next_secs(&self) -> u64 {
+    if self.jitter == 0 {
+      return self.base;
+    }
+    // splitmix64
+    let mut z = self.state.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    self.base.saturating_add(z % self.jitter.saturating_add(1))
+  }
+}
+
+/// Sheds load once `maxConnections` requests are in flight, with a jittered
+/// `Retry-After`. The permit moves into the response body, so streamed
+/// downloads and status streams count until they finish.
+async fn limit_connections(
+  State(limit): State<Arc<ConnectionLimit>>,
+  req: Request<Body>,
+  next: Next,
+) -> Response {
+  let Ok(permit) = limit.permits.clone().try_acquire_owned() else {
+    let retry_after = HeaderValue::from(limit.retry_after.next_secs());
+    return (
+      StatusCode::SERVICE_UNAVAILABLE,
+      [(header::RETRY_AFTER, retry_after)],
+      "server is busy",
+    )
+      .into_response();
+  };
+  let (parts, body) = next.run(req).await.into_parts();
+  let body = body.into_data_stream().map(move |chunk| {
+    let _permit = &permit;
+    chunk
+  });
+  Response::from_parts(parts, Body::from_stream(body))
+}
+
+async fn bind_listener(
+  addr: SocketAddr,
+  settings: &GatewaySettings,
+) -> Result<tokio::net::TcpListener> {
+  let Some(backlog) = settings.listen_backlog else {
+    return Ok(tokio::net::TcpListener::bind(addr).await?);
+  };
+  let socket = if addr.is_ipv4() {
+    tokio::net::TcpSocket::new_v4()?
+  } else {
+    tokio::net::TcpSocket::new_v6()?
+  };
+  #[cfg(unix)]
+  socket.set_reuseaddr(true)?;
+  socket.bind(addr)?;
+  socket.listen(backlog).context("listen")
+}
+
+/// Applies socket options read once at startup. `SO_KEEPALIVE` is set on the
+/// listening socket and inherited by accepted connections on Linux, macOS
+/// and Windows; other platforms may ignore it.
+fn tune_listener(listener: &tokio::net::TcpListener, settings: &GatewaySettings) -> Result<()> {
+  if let Some(secs) = settings.tcp_keepalive_secs {
+    let keepalive = socket2::TcpKeepalive::new().with_time(Duration::from_secs(secs));
+    socket2::SockRef::from(listener)
+      .set_tcp_keepalive(&keepalive)
+      .context("set SO_KEEPALIVE on listener")?;
+  }
+  Ok(())
+}
+
+fn normalize_listen_addr(raw: &str) -> Result<SocketAddr> {
+  let raw = raw.trim();
+  if raw.is_empty() {
+    return Err(anyhow!("LISTEN_ADDR is empty"));
+  }
+
+  if let Some(port) = raw.strip_prefix(':') {
+    let port: u16 = port
+      .parse()
+      .with_context(|| format!("invalid port in LISTEN_ADDR {:?}", raw))?;
+    return Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port));
+  }
+
+  raw
+    .parse::<SocketAddr>()
+    .with_context(|| format!("invalid LISTEN_ADDR {:?}", raw))
+}
+
+/// Unrouted paths go to the selected server when it has `passthroughUi`,
+/// otherwise to the static frontend.
+async fn handle_fallback(
+  State(state): State<AppState>,
+  jar: CookieJar,
+  req: Request<Body>,
+) -> Response {
+  if passthrough_ui_selected(&state, &jar, req.headers()).await {
+    return handle_proxy(State(state), jar, req).await;
+  }
+  let precompressed = state.catalog.read().await.settings.precompressed_static.unwrap_or(false);
+  let mut static_service = ServeDir::new(&*state.static_dir)
+    .fallback(any(handle_spa_fallback).with_state(state.clone()));
+  if precompressed {
+    static_service = static_service.precompressed_br().precompressed_gzip();
+  }
+  match static_service.oneshot(req).await {
+    Ok(v) => v.into_response(),
+    Err(err) => match err {},
+  }
+}
+
+async fn handle_headless_fallback(
+  State(state): State<AppState>,
+  jar: CookieJar,
+  req: Request<Body>,
+) -> Response {
+  if passthrough_ui_selected(&state, &jar, req.headers()).await {
+    return handle_proxy(State(state), jar, req).await;
+  }
+  (StatusCode::NOT_FOUND, "not found").into_response()
+}
+
+/// Describes the gateway in place of the frontend in headless mode.
+async fn handle_headless_index(
+  State(state): State<AppState>,
+  jar: CookieJar,
+  req: Request<Body>,
+) -> Response {
+  if passthrough_ui_selected(&state, &jar, req.headers()).await {
+    return handle_proxy(State(state), jar, req).await;
+  }
+  let endpoints = HEADLESS_ENDPOINTS
+    .iter()
+    .map(|(method, path)| serde_json::json!({ "method": method, "path": path }))
+    .collect::<Vec<_>>();
+  let out = serde_json::json!({
+    "schema": SCHEMA_VERSION,
+    "name": "torrentmix-gateway",
+    "version": env!("CARGO_PKG_VERSION"),
+    "headless": true,
+    "endpoints": endpoints,
+  });
+  (
+    [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+    Json(out),
+  )
+    .into_response()
+}
+
+async fn passthrough_ui_selected(state: &AppState, jar: &CookieJar, headers: &HeaderMap) -> bool {
+  let catalog = state.catalog.read().await;
+  let health = state.health.read().await;
+  catalog.pick_for_request(jar, headers, &health).is_ok_and(|e| e.cfg.passthrough_ui)
+}
+
+/// Paths with no static file: browser navigations get the SPA, anything
+/// else (missing `.js`/`.css`, API typos) gets a real `404`.
+async fn handle_spa_fallback(State(state): State<AppState>, req: Request<Body>) -> Response {
+  if !accepts_html(req.headers()) {
+    return (StatusCode::NOT_FOUND, "not found").into_response();
+  }
+  serve_index(&state, req).await
+}
+
+fn accepts_html(headers: &HeaderMap) -> bool {
+  headers
+    .get_all(header::ACCEPT)
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .any(|v| v.contains("text/html") || v.contains("application/xhtml+xml"))
+}
+
+async fn handle_index(
+  State(state): State<AppState>,
+  jar: CookieJar,
+  req: Request<Body>,
+) -> Response {
+  if passthrough_ui_selected(&state, &jar, req.headers()).await {
+    return handle_proxy(State(state), jar, req).await;
+  }
+  serve_index(&state, req).await
+}
+
+async fn serve_index(state: &AppState, req: Request<Body>) -> Response {
+  let settings = state.catalog.read().await.settings.clone();
+  let Some(base_path) = settings.base_path() else {
+    let mut index = ServeFile::new(&*state.index_path);
+    if settings.precompressed_static.unwrap_or(false) {
+      index = index.precompressed_br().precompressed_gzip();
+    }
+    let resp = match index.oneshot(req).await {
+      Ok(v) => v.into_response(),
+      Err(err) => match err {},
+    };
+    if resp.status() != StatusCode::NOT_FOUND {
+      return resp;
+    }
+    return fallback_page(&settings).await;
+  };
+
+  let raw = match tokio::fs::read(&*state.index_path).await {
+    Ok(v) => v,
+    Err(_) => {
+      return fallback_page(&settings).await;
+    }
+  };
+  let html = rewrite_index_html(&String::from_utf8_lossy(&raw), &base_path);
+
+  (
+    [
+      (header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8")),
+      (header::CACHE_CONTROL, HeaderValue::from_static("no-cache")),
+    ],
+    html,
+  )
+    .into_response()
+}
+
+/// Served with `200` when the frontend build is missing, so a misconfigured
+/// `STATIC_DIR` shows an explanation instead of a bare error.
+async fn fallback_page(settings: &GatewaySettings) -> Response {
+  let custom = match &settings.fallback_page {
+    Some(path) => tokio::fs::read_to_string(path).await.ok(),
+    None => None,
+  };
+  tracing::warn!("index.html not found, serving fallback page");
+
+  (
+    [
+      (header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8")),
+      (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+    ],
+    custom.unwrap_or_else(|| FALLBACK_INDEX_HTML.to_string()),
+  )
+    .into_response()
+}
+
+async fn handle_version() -> impl IntoResponse {
+  let out = VersionResponse {
+    schema: SCHEMA_VERSION,
+    version: env!("CARGO_PKG_VERSION"),
+    commit: option_env!("GATEWAY_GIT_COMMIT"),
+  };
+
+  (
+    [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+    Json(out),
+  )
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RouteQuery {
+  path: String,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct StatusQuery {
+  /// Probes every server now instead of reading the health monitor's cache.
+  #[serde(default)]
+  fresh: bool,
+}
+
+async fn handle_status(
+  State(state): State<AppState>,
+  Query(query): Query<StatusQuery>,
+  jar: CookieJar,
+) -> impl IntoResponse {
+  let out = build_status(&state, &jar, query.fresh).await;
+  (
+    [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+    Json(out),
+  )
+}
+
+/// The server a proxied request for `path` would go to, resolved like
+/// [`proxy_request`] does (selection cookie, `X-TorrentMix-Server`,
+/// `strictRouteTyping`) without contacting any backend.
+async fn handle_route(
+  State(state): State<AppState>,
+  Query(query): Query<RouteQuery>,
+  jar: CookieJar,
+  headers: HeaderMap,
+) -> Response {
+  let path = query.path.split(['?', '#']).next().unwrap_or_default();
+  if !path.starts_with('/') {
+    return (StatusCode::BAD_REQUEST, "path must start with '/'").into_response();
+  }
+  let catalog = state.catalog.read().await;
+  if !is_proxied_path(path, &catalog.settings.proxy_prefixes()) {
+    return (StatusCode::BAD_REQUEST, "path is not a proxied route").into_response();
+  }
+  let health = state.health.read().await;
+  let entry = match catalog.pick_for_route(&jar, &headers, &health, path) {
+    Ok(v) => v,
+    Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+  };
+  let out = RouteResponse {
+    schema: SCHEMA_VERSION,
+    path: path.to_string(),
+    server_id: entry.cfg.id.clone(),
+    kind: entry.cfg.kind,
+    route_mismatch: catalog.settings.route_mismatch == Some(RouteMismatch::Reject)
+      && reject_route_mismatch(entry, path).is_some(),
+  };
+  (
+    [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+    Json(out),
+  )
+    .into_response()
+}
+
+/// Just the reachability part of `/status`, keyed by server id, for pollers
+/// that don't need names and URLs.
+async fn handle_reachability(
+  State(state): State<AppState>,
+  Query(query): Query<StatusQuery>,
+  jar: CookieJar,
+) -> impl IntoResponse {
+  let status = build_status(&state, &jar, query.fresh).await;
+  let servers = status
+    .servers
+    .into_iter()
+    .map(|s| {
+      let reachability = Reachability {
+        reachable: s.reachable,
+        latency_ms: s.latency_ms,
+      };
+      (s.id, reachability)
+    })
+    .collect();
+  let out = ReachabilityResponse {
+    schema: SCHEMA_VERSION,
+    servers,
+  };
+  (
+    [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+    Json(out),
+  )
+}
+
+/// Prometheus text exposition of [`Metrics`] for the configured servers.
+async fn handle_metrics(State(state): State<AppState>) -> Response {
+  let (kinds, queue_depths) = {
+    let catalog = state.catalog.read().await;
+    let kinds = catalog
+      .servers
+      .iter()
+      .map(|(id, entry)| (id.clone(), entry.cfg.kind))
+      .collect::<HashMap<_, _>>();
+    let depths = catalog
+      .servers
+      .iter()
+      .filter_map(|(id, entry)| Some((id.clone(), entry.queue.as_ref()?.depth())))
+      .collect::<BTreeMap<_, _>>();
+    (kinds, depths)
+  };
+  (
+    [
+      (header::CONTENT_TYPE, HeaderValue::from_static("text/plain; version=0.0.4")),
+      (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+    ],
+    state.metrics.render(&kinds, &queue_depths),
+  )
+    .into_response()
+}
+
+/// Cached qBittorrent sessions of the configured servers that log in, in
+/// config order. Cookie values are never included.
+async fn handle_sessions(State(state): State<AppState>) -> Response {
+  let ids = {
+    let catalog = state.catalog.read().await;
+    catalog
+      .order
+      .iter()
+      .filter(|id| catalog.servers[*id].cfg.needs_qbit_login())
+      .cloned()
+      .collect::<Vec<_>>()
+  };
+  let mut snapshot = state.qbit.snapshot().await;
+  let servers = ids
+    .into_iter()
+    .map(|id| {
+      let info = snapshot.remove(&id).unwrap_or_default();
+      SessionInfo { id, ..info }
+    })
+    .collect();
+  let out = SessionsResponse {
+    schema: SCHEMA_VERSION,
+    servers,
+  };
+  (
+    [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+    Json(out),
+  )
+    .into_response()
+}
+
+/// Aggregate of the health monitor's cached results for load balancers; never
+/// probes inline. Answers `503` when every monitored server is down.
+async fn handle_health(State(state): State<AppState>) -> Response {
+  let (up, down) = {
+    let health = state.health.read().await;
+    let up = health.values().filter(|h| h.reachable).count();
+    (up, health.len() - up)
+  };
+  let code = if up == 0 && down > 0 {
+    StatusCode::SERVICE_UNAVAILABLE
+  } else {
+    StatusCode::OK
+  };
+  let out = HealthResponse {
+    schema: SCHEMA_VERSION,
+    status: if down == 0 { "ok" } else { "degraded" },
+    servers: HealthCounts { up, down },
+  };
+  (
+    code,
+    [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+    Json(out),
+  )
+    .into_response()
+}
+
+/// Server-Sent Events feed of [`StatusResponse`] snapshots: one on connect,
+/// then one per background health check. Dropping the connection drops the
+/// stream and its subscription.
+async fn handle_status_stream(State(state): State<AppState>, jar: CookieJar) -> Response {
+  let Some(rx) = state.health_updates.as_ref().map(|tx| tx.subscribe()) else {
+    return (StatusCode::NOT_FOUND, "health monitor is disabled").into_response();
+  };
+
+  let stop = state.shutdown.clone().cancelled_owned();
+  let init = (state, jar, rx, true);
+  let stream = futures_util::stream::unfold(init, |(state, jar, mut rx, first)| async move {
+    if !first && rx.changed().await.is_err() {
+      return None;
+    }
+    let out = build_status(&state, &jar, false).await;
+    let event = Event::default().event("status").json_data(&out);
+    Some((event, (state, jar, rx, false)))
+  });
+  let stream = stream.take_until(stop);
+
+  (
+    [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+    Sse::new(stream).keep_alive(KeepAlive::default()),
+  )
+    .into_response()
+}
+
+/// With `fresh`, every enabled server is probed inline and the results also
+/// refresh the health monitor's cached entries.
+async fn build_status(state: &AppState, jar: &CookieJar, fresh: bool) -> StatusResponse {
+  let (selected, default_id, type_defaults, items, probe_timeout, retries, threshold, concurrency) = {
+    let catalog = state.catalog.read().await;
+    let selected = catalog.selected_id(jar, &*state.health.read().await).to_string();
+    let items = catalog
+      .order
+      .iter()
+      .filter_map(|id| catalog.servers.get(id))
+      .cloned()
+      .collect::<Vec<_>>();
+    let probe_timeout = catalog.settings.status_probe_timeout();
+    let threshold = catalog.settings.reachable_failure_threshold();
+    (
+      selected,
+      catalog.default_id.clone(),
+      catalog.type_defaults(),
+      items,
+      probe_timeout,
+      catalog.settings.status_connect_retries.unwrap_or(0),
+      threshold,
+      catalog.settings.status_concurrency(),
+    )
+  };
+  let maintenance = state.maintenance.read().await.clone();
+  let deadline = Instant::now() + probe_timeout;
+
+  // Servers the background monitor has not covered yet are probed inline.
+  let mut lat_map: HashMap<String, (Option<u64>, bool)> = HashMap::with_capacity(items.len());
+  if !fresh {
+    let health = state.health.read().await;
+    for entry in items.iter() {
+      if let Some(h) = health.get(&entry.cfg.id) {
+        lat_map.insert(entry.cfg.id.clone(), (h.latency_ms, h.reachable));
+      }
+    }
+  }
+
+  let mut tasks = Vec::with_capacity(items.len());
+  for entry in items
+    .iter()
+    .filter(|entry| entry.cfg.enabled && !lat_map.contains_key(&entry.cfg.id))
+  {
+    tasks.push(async move {
+      let (latency_ms, reachable) = measure_dial_latency(deadline, retries, entry).await;
+      (entry.cfg.id.clone(), latency_ms, reachable)
+    });
+  }
+
+  let results = futures_util::stream::iter(tasks)
+    .buffer_unordered(concurrency)
+    .collect::<Vec<_>>()
+    .await;
+  for (id, latency_ms, reachable) in results {
+    let reachable = state.debounce_reachable(&id, reachable, threshold).await;
+    state.metrics.record_probe(&id, latency_ms, reachable);
+    lat_map.insert(id, (latency_ms, reachable));
+  }
+  if fresh {
+    // Only servers the monitor already tracks: without a monitor the cache
+    // would otherwise never be refreshed again.
+    let mut health = state.health.write().await;
+    let mut changed = false;
+    for (id, h) in health.iter_mut() {
+      if let Some(&(latency_ms, reachable)) = lat_map.get(id) {
+        *h = Health { latency_ms, reachable };
+        changed = true;
+      }
+    }
+    drop(health);
+    if changed {
+      state.notify_health_update();
+    }
+  }
+
+  let qbit = &state.qbit;
+  let lat_ref = &lat_map;
+  let version_tasks = items
+    .iter()
+    .map(|entry| async move {
+      let reachable = lat_ref.get(&entry.cfg.id).is_some_and(|(_, reachable)| *reachable);
+      if entry.cfg.kind != BackendType::Qbit || !entry.cfg.enabled || !reachable {
+        return None;
+      }
+      qbit.api_version(entry, probe_timeout).await
+    })
+    .collect::<Vec<_>>();
+  let versions = futures_util::stream::iter(version_tasks)
+    .buffered(concurrency)
+    .collect::<Vec<_>>()
+    .await;
+
+  let mut servers = Vec::with_capacity(items.len());
+  for (entry, version) in items.into_iter().zip(versions) {
+    let (latency_ms, reachable) = lat_map
+      .get(&entry.cfg.id)
+      .cloned()
+      .unwrap_or((None, false));
+    servers.push(ServerPublic {
+      id: entry.cfg.id,
+      name: entry.cfg.name,
+      kind: entry.cfg.kind,
+      base_url: entry.cfg.base_url,
+      latency_ms,
+      reachable,
+      enabled: entry.cfg.enabled,
+      version,
+      color: entry.cfg.color,
+      icon: entry.cfg.icon,
+      source: entry.cfg.discovered.then_some("mdns"),
+    });
+  }
+
+  StatusResponse {
+    schema: SCHEMA_VERSION,
+    selected_id: selected,
+    default_id,
+    type_defaults,
+    maintenance: maintenance.enabled,
+    maintenance_message: (maintenance.enabled && !maintenance.message.is_empty())
+      .then_some(maintenance.message),
+    servers,
+  }
+}
+
+async fn handle_select(
+  State(state): State<AppState>,
+  req: Request<Body>,
+) -> Response {
+  let (id, headers) = match read_selection(&state, req).await {
+    Ok(v) => v,
+    Err(resp) => return resp,
+  };
+
+  let out = serde_json::json!({ "ok": true, "id": id });
+  (headers, Json(out)).into_response()
+}
+
+/// Selects a server and returns the resulting status in one round-trip, so
+/// clients can switch without a second request.
+async fn handle_select_and_status(
+  State(state): State<AppState>,
+  jar: CookieJar,
+  req: Request<Body>,
+) -> Response {
+  let (id, mut headers) = match read_selection(&state, req).await {
+    Ok(v) => v,
+    Err(resp) => return resp,
+  };
+  let jar = jar.add(axum_extra::extract::cookie::Cookie::new(COOKIE_SELECTED_SERVER, id));
+  let out = build_status(&state, &jar, false).await;
+  headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+  (headers, Json(out)).into_response()
+}
+
+/// Parses a [`SelectRequest`] body and validates the server id; returns it
+/// with the `Set-Cookie` header that stores the selection.
+async fn read_selection(
+  state: &AppState,
+  req: Request<Body>,
+) -> std::result::Result<(String, HeaderMap), Response> {
+  if req.method() != Method::POST {
+    return Err((StatusCode::METHOD_NOT_ALLOWED, "method not allowed").into_response());
+  }
+
+  let limit = state.catalog.read().await.settings.max_select_body_bytes();
+  let body = match read_body_bytes(req.into_body(), limit).await {
+    Ok(v) => v,
+    Err(_) => {
+      return Err((StatusCode::BAD_REQUEST, "invalid json body").into_response());
+    }
+  };
+
+  let parsed: SelectRequest = match serde_json::from_slice(&body) {
+    Ok(v) => v,
+    Err(_) => {
+      return Err((StatusCode::BAD_REQUEST, "invalid json body").into_response());
+    }
+  };
+
+  let id = parsed.id.trim().to_string();
+  if id.is_empty() {
+    return Err((StatusCode::BAD_REQUEST, "id is required").into_response());
+  }
+  let cookie_domain = {
+    let catalog = state.catalog.read().await;
+    match catalog.servers.get(&id) {
+      None => {
+        return Err((StatusCode::BAD_REQUEST, "unknown server id").into_response());
+      }
+      Some(entry) if !entry.cfg.enabled => {
+        return Err((StatusCode::BAD_REQUEST, "server is disabled").into_response());
+      }
+      Some(_) => {}
+    }
+    catalog.settings.cookie_domain.clone()
+  };
+
+  let mut headers = HeaderMap::new();
+  if let Ok(v) = HeaderValue::from_str(&selection_cookie(&id, cookie_domain.as_deref())) {
+    headers.insert(header::SET_COOKIE, v);
+  }
+  Ok((id, headers))
+}
+
+/// `Set-Cookie` value storing the selected server id.
+fn selection_cookie(id: &str, domain: Option<&str>) -> String {
+  let mut cookie = format!(
+    "{name}={value}; Path=/; HttpOnly; SameSite=Lax; Max-Age=31536000",
+    name = COOKIE_SELECTED_SERVER,
+    value = id
+  );
+  if let Some(domain) = domain {
+    cookie.push_str("; Domain=");
+    cookie.push_str(domain.trim());
+  }
+  cookie
+}
+
+async fn handle_maintenance(State(state): State<AppState>, req: Request<Body>) -> Response {
+  let limit = state.catalog.read().await.settings.max_config_body_bytes();
+  let body = match read_body_bytes(req.into_body(), limit).await {
+    Ok(v) => v,
+    Err(ReadBodyError::TooLarge) => {
+      return (StatusCode::PAYLOAD_TOO_LARGE, "request entity too large").into_response();
+    }
+    Err(_) => {
+      return (StatusCode::BAD_REQUEST, "read body failed").into_response();
+    }
+  };
+
+  let parsed: MaintenanceRequest = match serde_json::from_slice(&body) {
+    Ok(v) => v,
+    Err(_) => {
+      return (StatusCode::BAD_REQUEST, "invalid json body").into_response();
+    }
+  };
+
+  let next = Maintenance {
+    enabled: parsed.enabled,
+    message: parsed.message.map(|v| v.trim().to_string()).unwrap_or_default(),
+  };
+
+  if parsed.persist {
+    if let Some(resp) = config_locked(&state).await {
+      return resp;
+    }
+    if config_layers(&state.config_path).len() > 1 {
+      return layered_config_conflict();
+    }
+    let mut catalog = state.catalog.write().await;
+    let mut config = catalog.to_config();
+    config.settings.maintenance = next.enabled.then(|| next.clone());
+    if let Err(err) = write_config_file(&state.config_path, &config).await {
+      return write_config_failed(&err);
+    }
+    catalog.settings = config.settings;
+  }
+
+  tracing::info!(enabled = next.enabled, message = %next.message, "maintenance mode updated");
+  *state.maintenance.write().await = next.clone();
+
+  Json(serde_json::json!({ "ok": true, "enabled": next.enabled, "message": next.message }))
+    .into_response()
+}
+
+async fn handle_proxy(
+  State(state): State<AppState>,
+  jar: CookieJar,
+  req: Request<Body>,
+) -> Response {
+  let (access_log, max_uri_bytes) = {
+    let catalog = state.catalog.read().await;
+    (catalog.access_log.clone(), catalog.settings.max_uri_bytes())
+  };
+  let uri_len = req.uri().path_and_query().map_or(0, |v| v.as_str().len());
+  if uri_len > max_uri_bytes {
+    return (StatusCode::URI_TOO_LONG, format!("URI too long (max {max_uri_bytes} bytes)"))
+      .into_response();
+  }
+  let Some(redactor) = access_log else {
+    return proxy_request(state, jar, req).await;
+  };
+
+  let start = Instant::now();
+  let method = req.method().clone();
+  let path = req.uri().path().to_string();
+  let query = req.uri().query().map(|q| redactor.form(q.as_bytes())).unwrap_or_default();
+  // Small form bodies (e.g. qBittorrent logins and preferences) are
+  // buffered here so their fields can be logged; other bodies are left to
+  // the proxy and its per-server limits.
+  let declared = req
+    .headers()
+    .get(header::CONTENT_LENGTH)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.parse::<usize>().ok());
+  let (req, body) = match declared {
+    Some(len) if len <= MAX_LOGGED_BODY_BYTES && is_form_urlencoded(req.headers()) => {
+      let (parts, body) = req.into_parts();
+      let Ok(bytes) = read_body_bytes(body, MAX_LOGGED_BODY_BYTES).await else {
+        return (StatusCode::BAD_REQUEST, "read body failed").into_response();
+      };
+      let logged = redactor.form(&bytes);
+      (Request::from_parts(parts, Body::from(bytes)), logged)
+    }
+    Some(len) if is_form_urlencoded(req.headers()) => (req, format!("<{len} bytes>")),
+    _ => (req, String::new()),
+  };
+
+  let resp = proxy_request(state, jar, req).await;
+  tracing::info!(
+    method = %method,
+    path = %path,
+    query = %query,
+    body = %body,
+    status = resp.status().as_u16(),
+    duration_ms = start.elapsed().as_millis() as u64,
+    "proxied request"
+  );
+  resp
+}
+
+async fn proxy_request(state: AppState, jar: CookieJar, mut req: Request<Body>) -> Response {
+  let Some(uri) = normalized_uri(req.uri()) else {
+    return (StatusCode::BAD_REQUEST, "invalid path").into_response();
+  };
+  *req.uri_mut() = uri;
+  let client_ip = req
+    .extensions()
+    .get::<ConnectInfo<SocketAddr>>()
+    .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |info| info.0.ip());
+  let from_proxy = state.trusted_proxies.iter().any(|net| net.contains(&client_ip.to_canonical()));
+  if let Some(resp) = reject_in_maintenance(&state).await {
+    return resp;
+  }
+
+  let (entry, rules, route_mismatch, coalesce) = {
+    let catalog = state.catalog.read().await;
+    let health = state.health.read().await;
+    match catalog.pick_for_route(&jar, req.headers(), &health, req.uri().path()) {
+      Ok(v) => (
+        v.clone(),
+        catalog.response_headers.clone(),
+        catalog.settings.route_mismatch,
+        catalog.settings.coalesce_gets.unwrap_or(false),
+      ),
+      Err(msg) => {
+        return (StatusCode::BAD_REQUEST, msg).into_response();
+      }
+    }
+  };
+  let entry = if entry.cfg.kind == BackendType::Auto {
+    resolve_auto_servers(&state, Some(&entry.cfg.id)).await;
+    let catalog = state.catalog.read().await;
+    match catalog.servers.get(&entry.cfg.id) {
+      Some(v) if v.cfg.kind != BackendType::Auto => v.clone(),
+      _ => return (StatusCode::BAD_GATEWAY, "backend type not detected").into_response(),
+    }
+  } else {
+    entry
+  };
+
+  if route_mismatch == Some(RouteMismatch::Reject) {
+    if let Some(resp) = reject_route_mismatch(&entry, req.uri().path()) {
+      return resp;
+    }
+  }
+  let confirmed = confirms_destructive(req.headers());
+  if let Some(resp) = reject_by_policy(&entry, req.uri().path(), confirmed) {
+    return resp;
+  }
+
+  // Held until the response head is ready.
+  let _permit = match &entry.queue {
+    Some(queue) => Some(queue.acquire(client_ip).await),
+    None => None,
+  };
+
+  let mut cache = None;
+  if !entry.cache_rules.is_empty() {
+    if req.method() != Method::GET {
+      invalidate_cached_responses(&state, &entry.cfg.id, req.uri().path()).await;
+    } else if let Some(ttl) = entry.cache_ttl(req.uri().path()) {
+      let key = ResponseCacheKey::new(&entry.cfg.id, req.uri(), req.headers());
+      if let Some(hit) = cached_response(&state, &key).await {
+        return respond_from_cache(hit, &rules);
+      }
+      cache = Some((key, ttl));
+    }
+  }
+
+  let method = req.method().clone();
+  let uri = req.uri().clone();
+  let mut headers = req.headers().clone();
+  set_forwarded_for(&mut headers, &entry, client_ip);
+
+  let coalesce = coalesce && cache.is_none() && entry.command.is_none() && method == Method::GET;
+  let flight = if coalesce && !has_request_body(&headers) {
+    join_flight(&state, ResponseCacheKey::new(&entry.cfg.id, &uri, &headers)).await
+  } else {
+    Flight::Alone
+  };
+  let leader = match flight {
+    Flight::Shared(shared) => return respond_shared(shared, &rules),
+    Flight::Leader(v) => Some(v),
+    Flight::Alone => None,
+  };
+
+  if is_multipart(&headers) && entry.command.is_none() {
+    let body = req.into_body();
+    return proxy_streaming_upload(&state, &entry, &rules, &method, &uri, &headers, body).await;
+  }
+
+  let limit = usize::try_from(entry.max_upload_bytes).unwrap_or(usize::MAX);
+  let body = match read_body_bytes(req.into_body(), limit).await {
+    Ok(v) => v,
+    Err(ReadBodyError::TooLarge) => return body_too_large(entry.max_upload_bytes),
+    Err(_) => {
+      return (StatusCode::BAD_REQUEST, "read body failed").into_response();
+    }
+  };
+  let validate_rpc = entry.cfg.validate_rpc && entry.cfg.kind == BackendType::Trans;
+  if validate_rpc && is_rpc_call(&method, &uri) {
+    if let Err(msg) = check_rpc_body(&body) {
+      return (StatusCode::BAD_REQUEST, format!("invalid RPC request: {msg}")).into_response();
+    }
+  }
+  if !confirmed && is_destructive_rpc(&entry, &body) {
+    return destructive_not_confirmed();
+  }
+  if let Some(pool) = &entry.command {
+    return forward_command(pool, &entry, &rules, &method, &uri, &headers, &body).await;
+  }
+
+  let forwarded = from_proxy.then_some(&headers);
+  let resp = forward_with_session(&state, &entry, &method, &uri, &headers, forwarded, body).await;
+  let resp = match resp {
+    Ok(v) => v,
+    Err(err) => return (StatusCode::BAD_GATEWAY, err.to_string()).into_response(),
+  };
+  if let Some(leader) = leader {
+    return share_upstream_response(leader, &entry, &rules, resp).await;
+  }
+  match cache {
+    Some((key, ttl)) if resp.status() == StatusCode::OK => {
+      cache_upstream_response(&state, &entry, key, ttl, &rules, resp).await
+    }
+    _ if is_rewritable(&entry, resp.headers()) => rewrite_response(&entry, &rules, resp).await,
+    _ => proxy_response(&entry, &rules, resp),
+  }
+}
+
+fn has_request_body(headers: &HeaderMap) -> bool {
+  headers.contains_key(header::TRANSFER_ENCODING)
+    || headers.get(header::CONTENT_LENGTH).is_some_and(|v| v != "0")
+}
+
+/// Makes the caller the leader for `key`, or waits for the running leader
+/// and takes its response.
+async fn join_flight(state: &AppState, key: ResponseCacheKey) -> Flight {
+  let mut rx = {
+    let mut flights = state.flights.lock().expect("flight lock");
+    match flights.get(&key) {
+      Some(rx) => rx.clone(),
+      None => {
+        let (tx, rx) = watch::channel(None);
+        flights.insert(key.clone(), rx);
+        return Flight::Leader(FlightLeader {
+          flights: state.flights.clone(),
+          key,
+          tx,
+        });
+      }
+    }
+  };
+  let shared = match rx.wait_for(Option::is_some).await {
+    Ok(result) => result.clone().flatten(),
+    Err(_) => None,
+  };
+  shared.map_or(Flight::Alone, Flight::Shared)
+}
+
+/// Buffers the leader's response and hands it to the waiting requests.
+/// Responses that set cookies or exceed `inspectBodyMaxBytes` are not shared;
+/// the waiters then send their own requests.
+async fn share_upstream_response(
+  leader: FlightLeader,
+  entry: &ServerEntry,
+  rules: &ResponseHeaderRules,
+  resp: reqwest::Response,
+) -> Response {
+  if resp.headers().contains_key(header::SET_COOKIE) {
+    leader.publish(None);
+    if is_rewritable(entry, resp.headers()) {
+      return rewrite_response(entry, rules, resp).await;
+    }
+    return proxy_response(entry, rules, resp);
+  }
+  let status = resp.status();
+  let rewrite = is_rewritable(entry, resp.headers());
+  let mut headers = sanitize_response_headers(resp.headers().clone());
+  if status.is_redirection() {
+    rewrite_location_header(entry, &mut headers);
+  }
+  let mut body = match inspect_body(resp, entry.inspect_body_max_bytes).await {
+    Ok(InspectedBody::Complete(v)) => v,
+    Ok(InspectedBody::Streaming(body)) => {
+      leader.publish(None);
+      rules.apply(&mut headers);
+      let mut out = Response::new(body);
+      *out.status_mut() = status;
+      *out.headers_mut() = headers;
+      return out;
+    }
+    Err(err) => {
+      return (StatusCode::BAD_GATEWAY, format!("read upstream body: {err}")).into_response();
+    }
+  };
+  if rewrite {
+    body = rewrite_body(entry, body);
+    headers.remove(header::CONTENT_LENGTH);
+  }
+  let shared = SharedResponse {
+    status,
+    headers,
+    body,
+  };
+  leader.publish(Some(shared.clone()));
+  respond_shared(shared, rules)
 }
 
-#[derive(Debug, serde::Serialize)]
-#[serde(rename_all = "camelCase")]
-struct ConfigResponse {
-  schema: u32,
-  default_server_id: String,
-  servers: Vec<ConfigServerPublic>,
+fn respond_shared(shared: SharedResponse, rules: &ResponseHeaderRules) -> Response {
+  let mut headers = shared.headers;
+  rules.apply(&mut headers);
+  let mut out = Response::new(Body::from(shared.body));
+  *out.status_mut() = shared.status;
+  *out.headers_mut() = headers;
+  out
 }
 
-#[derive(Debug, serde::Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ConfigUpdateRequest {
-  #[serde(default)]
-  default_server_id: String,
-  servers: Vec<ConfigUpdateServer>,
+/// Hands a request to a `command` backend and relays its reply.
+async fn forward_command(
+  pool: &CommandPool,
+  entry: &ServerEntry,
+  rules: &ResponseHeaderRules,
+  method: &Method,
+  uri: &Uri,
+  headers: &HeaderMap,
+  body: &[u8],
+) -> Response {
+  let mut out_headers = sanitize_request_headers(headers.clone());
+  for name in &entry.strip_request_headers {
+    out_headers.remove(name);
+  }
+  if let Some(v) = &entry.user_agent {
+    out_headers.insert(header::USER_AGENT, v.clone());
+  }
+  let req = CommandRequest {
+    method: method.as_str(),
+    path: uri.path_and_query().map_or(uri.path(), |v| v.as_str()),
+    headers: out_headers
+      .iter()
+      .filter_map(|(k, v)| Some((k.as_str(), v.to_str().ok()?)))
+      .collect(),
+    body: BASE64.encode(body),
+  };
+  let resp = match pool.call(&req).await {
+    Ok(v) => v,
+    Err(err) => {
+      tracing::warn!(server = %entry.cfg.id, error = %format!("{err:#}"), "command backend failed");
+      return (StatusCode::BAD_GATEWAY, format!("{err:#}")).into_response();
+    }
+  };
+  let Ok(status) = StatusCode::from_u16(resp.status) else {
+    return (StatusCode::BAD_GATEWAY, "command returned an invalid status").into_response();
+  };
+  let Ok(body) = BASE64.decode(resp.body.as_bytes()) else {
+    return (StatusCode::BAD_GATEWAY, "command returned an invalid body").into_response();
+  };
+  let mut out_headers = HeaderMap::with_capacity(resp.headers.len());
+  for (name, value) in &resp.headers {
+    if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+      out_headers.append(name, value);
+    }
+  }
+  let mut out_headers = sanitize_response_headers(out_headers);
+  out_headers.remove(header::CONTENT_LENGTH);
+  rules.apply(&mut out_headers);
+
+  let mut out = Response::new(Body::from(body));
+  *out.status_mut() = status;
+  *out.headers_mut() = out_headers;
+  out
 }
 
-#[derive(Debug, serde::Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ConfigUpdateServer {
-  id: String,
-  #[serde(default)]
-  name: String,
-  #[serde(rename = "type")]
-  kind: BackendType,
-  base_url: String,
-  #[serde(default)]
-  username: String,
-  password: Option<String>,
+/// Replaces client-supplied forwarding headers, which could spoof the
+/// address a backend logs or rate-limits, with the socket peer address when
+/// `forwardClientIp` is enabled.
+fn set_forwarded_for(headers: &mut HeaderMap, entry: &ServerEntry, client_ip: IpAddr) {
+  headers.remove(header::FORWARDED);
+  headers.remove("x-forwarded-for");
+  headers.remove("x-real-ip");
+  if entry.cfg.forward_client_ip && !client_ip.is_unspecified() {
+    let value = HeaderValue::from_str(&client_ip.to_string()).expect("ip is a valid header");
+    headers.insert("x-forwarded-for", value.clone());
+    headers.insert("x-real-ip", value);
+  }
 }
 
-pub async fn serve_from_env() -> Result<()> {
-  let listen = env_or_default("LISTEN_ADDR", ":8080");
-  let static_dir = env_or_default("STATIC_DIR", "./dist");
-  let config_path = env_or_default("STANDALONE_CONFIG", "/config/standalone.json");
+/// `503` for backend requests while maintenance mode is on.
+async fn reject_in_maintenance(state: &AppState) -> Option<Response> {
+  let maintenance = state.maintenance.read().await;
+  if !maintenance.enabled {
+    return None;
+  }
+  let message = if maintenance.message.is_empty() {
+    "under maintenance"
+  } else {
+    maintenance.message.as_str()
+  };
+  let out = serde_json::json!({ "error": "maintenance", "message": message });
+  Some((StatusCode::SERVICE_UNAVAILABLE, Json(out)).into_response())
+}
 
-  serve(&listen, PathBuf::from(static_dir), PathBuf::from(config_path)).await
+/// Per-server checks for a request to `path`, shared by the proxy and the
+/// probe route: the server is enabled, `allowedPaths`/`deniedPaths` permit
+/// the path, and `destructivePaths` are confirmed. Destructive RPC methods
+/// are checked separately once the body has been read.
+fn reject_by_policy(entry: &ServerEntry, path: &str, confirmed: bool) -> Option<Response> {
+  if !entry.cfg.enabled {
+    return Some((StatusCode::BAD_REQUEST, "server is disabled").into_response());
+  }
+  if entry.path_rules.as_ref().is_some_and(|r| !r.allows(path)) {
+    return Some((StatusCode::FORBIDDEN, "path is not allowed for this server").into_response());
+  }
+  if !confirmed && entry.destructive_paths.as_ref().is_some_and(|m| m.is_match(path)) {
+    return Some(destructive_not_confirmed());
+  }
+  None
+}
+
+fn confirms_destructive(headers: &HeaderMap) -> bool {
+  headers
+    .get(HEADER_CONFIRM_DESTRUCTIVE)
+    .and_then(|v| v.to_str().ok())
+    .is_some_and(|v| v.trim().eq_ignore_ascii_case("yes"))
+}
+
+/// Whether a Transmission RPC body calls one of `destructiveRpcMethods`.
+fn is_destructive_rpc(entry: &ServerEntry, body: &[u8]) -> bool {
+  if entry.cfg.destructive_rpc_methods.is_empty() {
+    return false;
+  }
+  let Ok(v) = serde_json::from_slice::<serde_json::Value>(body) else {
+    return false;
+  };
+  let Some(method) = v.get("method").and_then(|m| m.as_str()) else {
+    return false;
+  };
+  entry.cfg.destructive_rpc_methods.iter().any(|m| m.trim() == method)
+}
+
+/// `404` for a qBittorrent route sent to a Transmission server or the other
+/// way round. Other backend types and proxy prefixes are not checked.
+fn reject_route_mismatch(entry: &ServerEntry, path: &str) -> Option<Response> {
+  let (route, expected) = if path.starts_with("/api/") {
+    ("/api/*", BackendType::Qbit)
+  } else if path.starts_with("/transmission/") {
+    ("/transmission/*", BackendType::Trans)
+  } else {
+    return None;
+  };
+  let kind = entry.cfg.kind;
+  if kind == expected || !matches!(kind, BackendType::Qbit | BackendType::Trans) {
+    return None;
+  }
+  let message = format!(
+    "{route} is a {} route, but server {:?} is a {} backend; select a {} server",
+    expected.label(),
+    entry.cfg.id,
+    kind.label(),
+    expected.label()
+  );
+  let out = serde_json::json!({ "error": "route mismatch", "message": message });
+  Some((StatusCode::NOT_FOUND, Json(out)).into_response())
+}
+
+fn is_rpc_call(method: &Method, uri: &Uri) -> bool {
+  method == Method::POST && uri.path() == "/transmission/rpc"
+}
+
+/// Shape check for `validateRpc`: a JSON object with a non-empty string
+/// `method`, and object (or, for JSON-RPC 2.0 `params`, array) arguments.
+/// Method names themselves are not checked.
+fn check_rpc_body(body: &[u8]) -> std::result::Result<(), &'static str> {
+  let v = serde_json::from_slice::<serde_json::Value>(body).map_err(|_| "body is not JSON")?;
+  let obj = v.as_object().ok_or("body is not a JSON object")?;
+  match obj.get("method").and_then(|m| m.as_str()) {
+    Some(m) if !m.trim().is_empty() => {}
+    _ => return Err("missing string \"method\""),
+  }
+  if obj.get("arguments").is_some_and(|a| !a.is_object()) {
+    return Err("\"arguments\" is not an object");
+  }
+  if obj.get("params").is_some_and(|p| !p.is_object() && !p.is_array()) {
+    return Err("\"params\" is not an object or array");
+  }
+  Ok(())
+}
+
+fn destructive_not_confirmed() -> Response {
+  let out = serde_json::json!({
+    "error": "confirmation required",
+    "message": "destructive request requires the X-Confirm-Destructive: yes header",
+  });
+  (StatusCode::PRECONDITION_REQUIRED, Json(out)).into_response()
+}
+
+async fn cached_response(state: &AppState, key: &ResponseCacheKey) -> Option<CachedResponse> {
+  let mut responses = state.responses.lock().await;
+  match responses.get(key) {
+    Some(hit) if hit.expires_at > Instant::now() => Some(hit.clone()),
+    Some(_) => {
+      responses.remove(key);
+      None
+    }
+    None => None,
+  }
+}
+
+/// Buffers a cacheable `200` response and stores it unless it exceeds
+/// [`MAX_CACHED_RESPONSE_BYTES`]. Bodies over `inspectBodyMaxBytes` are
+/// streamed through uncached.
+async fn cache_upstream_response(
+  state: &AppState,
+  entry: &ServerEntry,
+  key: ResponseCacheKey,
+  ttl: Duration,
+  rules: &ResponseHeaderRules,
+  resp: reqwest::Response,
+) -> Response {
+  let rewrite = is_rewritable(entry, resp.headers());
+  let mut headers = sanitize_response_headers(resp.headers().clone());
+  let mut body = match inspect_body(resp, entry.inspect_body_max_bytes).await {
+    Ok(InspectedBody::Complete(v)) => v,
+    Ok(InspectedBody::Streaming(body)) => {
+      rules.apply(&mut headers);
+      let mut out = Response::new(body);
+      *out.headers_mut() = headers;
+      return out;
+    }
+    Err(err) => {
+      return (StatusCode::BAD_GATEWAY, format!("read upstream body: {err}")).into_response();
+    }
+  };
+  if rewrite {
+    body = rewrite_body(entry, body);
+    headers.remove(header::CONTENT_LENGTH);
+  }
+  let entry = CachedResponse {
+    expires_at: Instant::now() + ttl,
+    headers,
+    body,
+  };
+  if entry.body.len() <= MAX_CACHED_RESPONSE_BYTES {
+    let now = Instant::now();
+    let mut responses = state.responses.lock().await;
+    responses.retain(|_, v| v.expires_at > now);
+    responses.insert(key, entry.clone());
+  }
+  respond_from_cache(entry, rules)
+}
+
+fn respond_from_cache(cached: CachedResponse, rules: &ResponseHeaderRules) -> Response {
+  let mut headers = cached.headers;
+  rules.apply(&mut headers);
+  let mut out = Response::new(Body::from(cached.body));
+  *out.headers_mut() = headers;
+  out
+}
+
+/// A write such as `app/setPreferences` drops cached responses of the same
+/// server under the same parent path (`app/preferences`).
+async fn invalidate_cached_responses(state: &AppState, server: &str, path: &str) {
+  let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+  state.responses.lock().await.retain(|key, _| {
+    let cached = key.path_and_query.split('?').next().unwrap_or("");
+    key.server != server || cached.rsplit_once('/').map_or("", |(p, _)| p) != parent
+  });
+}
+
+/// Streams a `multipart/form-data` body (e.g. bulk `torrents/add`) straight
+/// to the backend instead of buffering it. The body cannot be replayed, so a
+/// `403` is relayed after refreshing the qBittorrent session for the
+/// client's retry.
+async fn proxy_streaming_upload(
+  state: &AppState,
+  entry: &ServerEntry,
+  rules: &ResponseHeaderRules,
+  method: &Method,
+  uri: &Uri,
+  headers: &HeaderMap,
+  body: Body,
+) -> Response {
+  let limit = entry.max_stream_upload_bytes;
+  let declared = headers
+    .get(header::CONTENT_LENGTH)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.parse::<u64>().ok());
+  if let (Some(limit), Some(declared)) = (limit, declared) {
+    if declared > limit {
+      return body_too_large(limit);
+    }
+  }
+
+  let mut cookie = None;
+  if entry.cfg.needs_qbit_login() {
+    cookie = state.qbit.ensure_cookie(entry, false).await.ok();
+  }
+
+  let mut seen: u64 = 0;
+  let exceeded = Arc::new(AtomicBool::new(false));
+  let flag = exceeded.clone();
+  let stream = body.into_data_stream().map(move |chunk| {
+    let chunk = chunk.map_err(std::io::Error::other)?;
+    seen = seen.saturating_add(chunk.len() as u64);
+    if limit.is_some_and(|limit| seen > limit) {
+      flag.store(true, Ordering::Relaxed);
+      return Err(std::io::Error::other("request entity too large"));
+    }
+    Ok::<Bytes, std::io::Error>(chunk)
+  });
+
+  let body = reqwest::Body::wrap_stream(stream);
+  match forward_once(state, entry, method, uri, headers, body, cookie.as_deref()).await {
+    Ok(resp) => {
+      if entry.cfg.needs_qbit_login() && resp.status() == StatusCode::FORBIDDEN {
+        state.metrics.record_reauth(&entry.cfg.id, "forbidden");
+        let _ = state.qbit.ensure_cookie(entry, true).await;
+      } else if cookie.is_some() {
+        state.qbit.apply_rotation(&entry.cfg.id, resp.headers()).await;
+      }
+      proxy_response(entry, rules, resp)
+    }
+    Err(_) if exceeded.load(Ordering::Relaxed) => body_too_large(limit.unwrap_or_default()),
+    Err(err) => (StatusCode::BAD_GATEWAY, err.to_string()).into_response(),
+  }
+}
+
+/// `413` for a proxied request body over the server's limit, naming the
+/// limit in `X-Max-Body-Bytes` and the message.
+fn body_too_large(limit: u64) -> Response {
+  (
+    StatusCode::PAYLOAD_TOO_LARGE,
+    [(HEADER_MAX_BODY_BYTES, HeaderValue::from(limit))],
+    format!("request entity too large (max {limit} bytes)"),
+  )
+    .into_response()
+}
+
+/// Whether `rewrite` rules apply to a response: textual, uncompressed and
+/// not declared larger than `inspectBodyMaxBytes`. Everything else, and
+/// bodies that turn out larger while reading, is streamed untouched.
+fn is_rewritable(entry: &ServerEntry, headers: &HeaderMap) -> bool {
+  if entry.cfg.rewrite.is_empty() {
+    return false;
+  }
+  let textual = headers
+    .get(header::CONTENT_TYPE)
+    .and_then(|v| v.to_str().ok())
+    .map(|v| v.trim_start().to_ascii_lowercase())
+    .is_some_and(|v| {
+      v.starts_with("text/")
+        || v.starts_with("application/json")
+        || v.starts_with("application/javascript")
+        || v.starts_with("application/xml")
+    });
+  let identity = headers
+    .get(header::CONTENT_ENCODING)
+    .is_none_or(|v| v.as_bytes().eq_ignore_ascii_case(b"identity"));
+  let small = headers
+    .get(header::CONTENT_LENGTH)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.parse::<usize>().ok())
+    .is_none_or(|n| n <= entry.inspect_body_max_bytes);
+  textual && identity && small
+}
+
+fn rewrite_body(entry: &ServerEntry, body: Bytes) -> Bytes {
+  let Ok(text) = std::str::from_utf8(&body) else {
+    return body;
+  };
+  let mut out = text.to_string();
+  for rule in &entry.cfg.rewrite {
+    out = out.replace(&rule.from, &rule.to);
+  }
+  Bytes::from(out)
+}
+
+/// Buffers a response for [`rewrite_body`].
+async fn rewrite_response(
+  entry: &ServerEntry,
+  rules: &ResponseHeaderRules,
+  resp: reqwest::Response,
+) -> Response {
+  let status = resp.status();
+  let mut headers = sanitize_response_headers(resp.headers().clone());
+  if status.is_redirection() {
+    rewrite_location_header(entry, &mut headers);
+  }
+  rules.apply(&mut headers);
+  headers.remove(header::CONTENT_LENGTH);
+  let body = match inspect_body(resp, entry.inspect_body_max_bytes).await {
+    Ok(InspectedBody::Complete(v)) => Body::from(rewrite_body(entry, v)),
+    Ok(InspectedBody::Streaming(body)) => body,
+    Err(err) => {
+      return (StatusCode::BAD_GATEWAY, format!("read upstream body: {err}")).into_response();
+    }
+  };
+
+  let mut out = Response::new(body);
+  *out.status_mut() = status;
+  *out.headers_mut() = headers;
+  out
+}
+
+/// Turns an upstream response into the client response, streaming the body.
+fn proxy_response(
+  entry: &ServerEntry,
+  rules: &ResponseHeaderRules,
+  resp: reqwest::Response,
+) -> Response {
+  let status = resp.status();
+  let mut out_headers = sanitize_response_headers(resp.headers().clone());
+  if status.is_redirection() {
+    rewrite_location_header(entry, &mut out_headers);
+  }
+  rules.apply(&mut out_headers);
+
+  let stream = resp
+    .bytes_stream()
+    .map_err(std::io::Error::other);
+  let body = Body::from_stream(stream);
+
+  let mut out = Response::new(body);
+  *out.status_mut() = status;
+  *out.headers_mut() = std::mem::take(&mut out_headers);
+  out
+}
+
+fn is_form_urlencoded(headers: &HeaderMap) -> bool {
+  headers
+    .get(header::CONTENT_TYPE)
+    .and_then(|v| v.to_str().ok())
+    .is_some_and(|v| {
+      v.trim_start()
+        .to_ascii_lowercase()
+        .starts_with("application/x-www-form-urlencoded")
+    })
+}
+
+fn is_multipart(headers: &HeaderMap) -> bool {
+  headers
+    .get(header::CONTENT_TYPE)
+    .and_then(|v| v.to_str().ok())
+    .is_some_and(|v| {
+      v.trim_start()
+        .to_ascii_lowercase()
+        .starts_with("multipart/form-data")
+    })
+}
+
+/// Live connectivity + auth check of every configured server, run
+/// concurrently under one deadline. Logins here bypass the session cache.
+async fn handle_config_test_all(State(state): State<AppState>) -> Response {
+  let items = {
+    let catalog = state.catalog.read().await;
+    catalog
+      .order
+      .iter()
+      .filter_map(|id| catalog.servers.get(id))
+      .cloned()
+      .collect::<Vec<_>>()
+  };
+
+  let deadline = Instant::now() + QBIT_LOGIN_TIMEOUT;
+  let state = &state;
+  let tasks = items.iter().map(|entry| async move {
+    let start = Instant::now();
+    let outcome = match timeout_at(deadline, test_server(state, entry)).await {
+      Ok(v) => v,
+      Err(_) => Err(anyhow!("timed out")),
+    };
+    let latency_ms = Some(start.elapsed().as_millis() as u64);
+    match outcome {
+      Ok(()) => ServerTestResult {
+        id: entry.cfg.id.clone(),
+        ok: true,
+        latency_ms,
+        error: None,
+      },
+      Err(err) => ServerTestResult {
+        id: entry.cfg.id.clone(),
+        ok: false,
+        latency_ms: None,
+        error: Some(format!("{err:#}")),
+      },
+    }
+  });
+  let servers = futures_util::future::join_all(tasks).await;
+
+  let out = TestAllResponse {
+    schema: SCHEMA_VERSION,
+    servers,
+  };
+  (
+    [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+    Json(out),
+  )
+    .into_response()
+}
+
+async fn test_server(state: &AppState, entry: &ServerEntry) -> Result<()> {
+  match entry.cfg.kind {
+    BackendType::Qbit if entry.cfg.needs_qbit_login() => {
+      state.qbit.login(entry).await.map_err(|err| match err {
+        LoginError::Transient(err) | LoginError::Rejected(err) => err,
+      })?;
+      Ok(())
+    }
+    BackendType::Qbit => {
+      let uri = Uri::from_static("/api/v2/app/webapiVersion");
+      let headers = HeaderMap::new();
+      let body = reqwest::Body::from("");
+      let resp = forward_once(state, entry, &Method::GET, &uri, &headers, body, None).await?;
+      if resp.status() != StatusCode::OK {
+        return Err(anyhow!("unexpected status {}", resp.status()));
+      }
+      Ok(())
+    }
+    BackendType::Trans => {
+      // Without a session id Transmission answers 409; 401 means bad credentials.
+      let uri = Uri::from_static("/transmission/rpc");
+      let headers = HeaderMap::new();
+      let body = reqwest::Body::from("");
+      let resp = forward_once(state, entry, &Method::POST, &uri, &headers, body, None).await?;
+      match resp.status() {
+        StatusCode::OK | StatusCode::CONFLICT => Ok(()),
+        StatusCode::UNAUTHORIZED => Err(anyhow!("authentication failed")),
+        status => Err(anyhow!("unexpected status {}", status)),
+      }
+    }
+    BackendType::Command => match &entry.command {
+      Some(pool) => pool.ping().await,
+      None => Err(anyhow!("command backend is not configured")),
+    },
+    BackendType::Auto => match detect_backend_type(state, entry).await {
+      Some(_) => Ok(()),
+      None => Err(anyhow!("backend type not detected")),
+    },
+  }
+}
+
+/// Collects application versions from every enabled server concurrently,
+/// under one shared deadline.
+async fn handle_aggregate_versions(State(state): State<AppState>) -> Response {
+  let items = {
+    let catalog = state.catalog.read().await;
+    catalog
+      .order
+      .iter()
+      .filter_map(|id| catalog.servers.get(id))
+      .filter(|entry| entry.cfg.enabled)
+      .cloned()
+      .collect::<Vec<_>>()
+  };
+
+  let deadline = Instant::now() + QBIT_LOGIN_TIMEOUT;
+  let state = &state;
+  let tasks = items.iter().map(|entry| async move {
+    let outcome = match timeout_at(deadline, fetch_versions(state, entry)).await {
+      Ok(v) => v,
+      Err(_) => Err(anyhow!("timed out")),
+    };
+    let (versions, error) = match outcome {
+      Ok(v) => (v, None),
+      Err(err) => (AppVersions::default(), Some(format!("{err:#}"))),
+    };
+    ServerVersions {
+      id: entry.cfg.id.clone(),
+      kind: entry.cfg.kind,
+      versions,
+      error,
+    }
+  });
+  let servers = futures_util::future::join_all(tasks).await;
+
+  let out = VersionsResponse {
+    schema: SCHEMA_VERSION,
+    servers,
+  };
+  (
+    [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+    Json(out),
+  )
+    .into_response()
+}
+
+/// Startup `prewarm`: a version query per enabled server opens its pooled
+/// connection and, for qBittorrent, logs in. Failures are only logged.
+async fn prewarm_servers(state: &AppState) {
+  let items = {
+    let catalog = state.catalog.read().await;
+    catalog
+      .order
+      .iter()
+      .filter_map(|id| catalog.servers.get(id))
+      .filter(|entry| entry.cfg.enabled && entry.command.is_none())
+      .cloned()
+      .collect::<Vec<_>>()
+  };
+
+  let started = Instant::now();
+  let deadline = started + QBIT_LOGIN_TIMEOUT;
+  let tasks = items.iter().map(|entry| async move {
+    match timeout_at(deadline, fetch_versions(state, entry)).await {
+      Ok(Ok(_)) => true,
+      Ok(Err(err)) => {
+        tracing::debug!(server = %entry.cfg.id, error = %format_args!("{err:#}"), "prewarm failed");
+        false
+      }
+      Err(_) => {
+        tracing::debug!(server = %entry.cfg.id, "prewarm timed out");
+        false
+      }
+    }
+  });
+  let results = futures_util::future::join_all(tasks).await;
+  let ok = results.iter().filter(|v| **v).count();
+  tracing::info!(
+    ok,
+    failed = results.len() - ok,
+    duration_ms = started.elapsed().as_millis() as u64,
+    "prewarmed upstream connections"
+  );
+}
+
+async fn fetch_versions(state: &AppState, entry: &ServerEntry) -> Result<AppVersions> {
+  match entry.cfg.kind {
+    // The envelope protocol has no version query.
+    BackendType::Command => Ok(AppVersions::default()),
+    BackendType::Auto => Err(anyhow!("backend type not detected")),
+    BackendType::Qbit => {
+      let get = |path: &'static str| async move {
+        let uri = Uri::from_static(path);
+        let headers = HeaderMap::new();
+        let resp =
+          forward_with_session(state, entry, &Method::GET, &uri, &headers, None, Vec::new()).await?;
+        if resp.status() != StatusCode::OK {
+          return Err(anyhow!("{path}: unexpected status {}", resp.status()));
+        }
+        match inspect_body(resp, entry.inspect_body_max_bytes).await {
+          Ok(InspectedBody::Complete(v)) => Ok(v),
+          Ok(InspectedBody::Streaming(_)) => Err(anyhow!("{path}: response too large")),
+          Err(err) => Err(err).with_context(|| format!("{path}: read body")),
+        }
+      };
+      let (version, build) =
+        tokio::join!(get("/api/v2/app/version"), get("/api/v2/app/buildInfo"));
+      let version = version?;
+      // buildInfo is missing on very old releases; the app version suffices.
+      let libtorrent_version = build
+        .ok()
+        .and_then(|v| serde_json::from_slice::<serde_json::Value>(&v).ok())
+        .and_then(|v| v.get("libtorrent")?.as_str().map(str::to_string));
+      Ok(AppVersions {
+        app_version: Some(String::from_utf8_lossy(&version).trim().to_string()),
+        libtorrent_version,
+        ..Default::default()
+      })
+    }
+    BackendType::Trans => {
+      let uri = Uri::from_static("/transmission/rpc");
+      let body = br#"{"method":"session-get","arguments":{"fields":["version","rpc-version"]}}"#;
+      let mut headers = HeaderMap::new();
+      headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+      let mut resp =
+        forward_once(state, entry, &Method::POST, &uri, &headers, body.to_vec().into(), None)
+          .await?;
+      if resp.status() == StatusCode::CONFLICT {
+        let id = resp
+          .headers()
+          .get(TRANSMISSION_SESSION_HEADER)
+          .cloned()
+          .ok_or_else(|| anyhow!("409 without {TRANSMISSION_SESSION_HEADER}"))?;
+        headers.insert(TRANSMISSION_SESSION_HEADER, id);
+        resp = forward_once(state, entry, &Method::POST, &uri, &headers, body.to_vec().into(), None)
+          .await?;
+      }
+      if resp.status() != StatusCode::OK {
+        return Err(anyhow!("unexpected status {}", resp.status()));
+      }
+      let body = match inspect_body(resp, entry.inspect_body_max_bytes).await {
+        Ok(InspectedBody::Complete(v)) => v,
+        Ok(InspectedBody::Streaming(_)) => return Err(anyhow!("session-get response too large")),
+        Err(err) => return Err(err).context("read session-get response"),
+      };
+      let v: serde_json::Value =
+        serde_json::from_slice(&body).context("invalid session-get response")?;
+      let args = v.get("arguments").ok_or_else(|| anyhow!("session-get returned no arguments"))?;
+      Ok(AppVersions {
+        app_version: args.get("version").and_then(|v| v.as_str()).map(str::to_string),
+        rpc_version: args.get("rpc-version").and_then(|v| v.as_u64()),
+        ..Default::default()
+      })
+    }
+  }
 }
 
-fn env_or_default(key: &str, default: &str) -> String {
-  let Ok(v) = std::env::var(key) else {
-    return default.to_string();
+/// Changes a server id in place: type defaults, the qBittorrent session and
+/// cached probe results move along, and the caller's selection cookie is
+/// rewritten when it pointed at the old id.
+async fn handle_server_rename(
+  State(state): State<AppState>,
+  AxumPath(id): AxumPath<String>,
+  jar: CookieJar,
+  req: Request<Body>,
+) -> Response {
+  if let Some(resp) = config_locked(&state).await {
+    return resp;
+  }
+  if let Some(resp) = require_json(req.headers()) {
+    return resp;
+  }
+  let if_match = req.headers().get(header::IF_MATCH).cloned();
+  let limit = state.catalog.read().await.settings.max_config_body_bytes();
+  let body = match read_body_bytes(req.into_body(), limit).await {
+    Ok(v) => v,
+    Err(ReadBodyError::TooLarge) => {
+      return (StatusCode::PAYLOAD_TOO_LARGE, "request entity too large").into_response();
+    }
+    Err(_) => {
+      return (StatusCode::BAD_REQUEST, "read body failed").into_response();
+    }
   };
-  let v = v.trim();
-  if v.is_empty() {
-    return default.to_string();
+  let parsed: RenameRequest = match serde_json::from_slice(&body) {
+    Ok(v) => v,
+    Err(_) => {
+      return (StatusCode::BAD_REQUEST, "invalid json body").into_response();
+    }
+  };
+  let new_id = parsed.new_id.trim().to_string();
+  if new_id.is_empty() {
+    return (StatusCode::BAD_REQUEST, "newId is required").into_response();
+  }
+  if config_layers(&state.config_path).len() > 1 {
+    return layered_config_conflict();
   }
-  v.to_string()
-}
 
-pub async fn serve(listen: &str, static_dir: PathBuf, config_path: PathBuf) -> Result<()> {
-  let addr = normalize_listen_addr(listen)?;
+  let discovered = state.discovered.lock().await.values().cloned().collect::<Vec<_>>();
+  let detected = state.detected_types.lock().await.clone();
+  let mut catalog = state.catalog.write().await;
+  if let Some(resp) = check_config_version(&catalog, if_match.as_ref()) {
+    return resp;
+  }
+  match catalog.servers.get(&id) {
+    None => return (StatusCode::NOT_FOUND, "unknown server id").into_response(),
+    Some(entry) if entry.cfg.discovered => {
+      return (StatusCode::BAD_REQUEST, "discovered servers cannot be renamed").into_response();
+    }
+    Some(_) => {}
+  }
+  if new_id != id && catalog.servers.contains_key(&new_id) {
+    return (StatusCode::CONFLICT, "newId is already in use").into_response();
+  }
 
-  let config_path = Arc::new(config_path);
+  let mut config = catalog.to_config();
+  for server in config.servers.iter_mut().filter(|s| s.id == id) {
+    server.id = new_id.clone();
+  }
+  for default in [
+    Some(&mut config.default_server_id),
+    config.default_qbit_id.as_mut(),
+    config.default_trans_id.as_mut(),
+  ]
+  .into_iter()
+  .flatten()
+  {
+    if *default == id {
+      *default = new_id.clone();
+    }
+  }
+  let runtime = with_detected(with_discovered(config.clone(), discovered), &detected);
+  let new_catalog = match Catalog::from_config(runtime) {
+    Ok(v) => v,
+    Err(err) => {
+      return (StatusCode::BAD_REQUEST, format!("config is invalid: {err:#}")).into_response();
+    }
+  };
+  if let Err(err) = write_config_file(&state.config_path, &config).await {
+    return write_config_failed(&err);
+  }
+  // Move per-server state before releasing the catalog, so no request sees
+  // the new id without its session.
+  state.qbit.rename(&id, &new_id).await;
+  {
+    let mut health = state.health.write().await;
+    if let Some(h) = health.remove(&id) {
+      health.insert(new_id.clone(), h);
+    }
+  }
+  let etag = config_etag(&config);
+  *catalog = new_catalog;
+  drop(catalog);
 
-  let catalog = Catalog::load(&config_path)?;
-  let catalog = Arc::new(RwLock::new(catalog));
+  state.probe_failures.lock().await.remove(&id);
+  state.favicons.lock().await.remove(&id);
+  state.responses.lock().await.retain(|key, _| key.server != id);
+  tracing::info!(from = %id, to = %new_id, "server renamed");
 
-  let qbit = Arc::new(QbitSessions::new()?);
-  let client = reqwest::Client::builder()
-    .timeout(Duration::from_secs(60))
-    .redirect(Policy::none())
-    .build()
-    .context("build proxy http client")?;
+  let mut headers = HeaderMap::new();
+  if jar.get(COOKIE_SELECTED_SERVER).is_some_and(|c| c.value().trim() == id) {
+    let cookie_domain = state.catalog.read().await.settings.cookie_domain.clone();
+    if let Ok(v) = HeaderValue::from_str(&selection_cookie(&new_id, cookie_domain.as_deref())) {
+      headers.insert(header::SET_COOKIE, v);
+    }
+  }
+  headers.insert(header::ETAG, etag);
+  (headers, Json(serde_json::json!({ "ok": true, "id": new_id }))).into_response()
+}
 
-  let state = AppState {
-    catalog,
-    qbit,
-    client,
-    config_path,
+/// Serves a backend's favicon through the gateway so the browser never
+/// contacts internal origins. Hits are cached for [`FAVICON_TTL`]; servers
+/// without an icon get a neutral placeholder, cached for
+/// [`FAVICON_MISS_TTL`].
+async fn handle_server_favicon(
+  State(state): State<AppState>,
+  AxumPath(id): AxumPath<String>,
+) -> Response {
+  let entry = {
+    let catalog = state.catalog.read().await;
+    match catalog.servers.get(id.trim()) {
+      Some(v) => v.clone(),
+      None => {
+        return (StatusCode::NOT_FOUND, "unknown server id").into_response();
+      }
+    }
   };
 
-  let index_path = static_dir.join("index.html");
-  let static_service = ServeDir::new(static_dir).fallback(ServeFile::new(index_path));
-
-  let app = Router::new()
-    .route("/__standalone__/status", get(handle_status))
-    .route("/__standalone__/select", post(handle_select))
-    .route("/__standalone__/config", get(handle_config_get).post(handle_config_update))
-    .route("/api/*path", any(handle_proxy))
-    .route("/transmission/*path", any(handle_proxy))
-    .fallback_service(static_service)
-    .with_state(state);
+  let cached = state.favicons.lock().await.get(&entry.cfg.id).cloned();
+  let icon = match cached {
+    Some(v) if v.is_fresh() => v.icon,
+    _ => {
+      let timeout = state.catalog.read().await.settings.status_probe_timeout();
+      let fetched = tokio::time::timeout(timeout, fetch_favicon(&state, &entry))
+        .await
+        .ok()
+        .flatten();
+      let cached = CachedFavicon {
+        fetched_at: Instant::now(),
+        icon: fetched.clone(),
+      };
+      state.favicons.lock().await.insert(entry.cfg.id.clone(), cached);
+      fetched
+    }
+  };
 
-  tracing::info!(listen = %addr, "standalone-service listening");
-  axum::serve(tokio::net::TcpListener::bind(addr).await?, app.into_make_service())
-    .await
-    .context("http server error")
+  match icon {
+    Some(v) => (
+      [
+        (header::CONTENT_TYPE, v.content_type),
+        (header::CACHE_CONTROL, HeaderValue::from_static("private, max-age=3600")),
+      ],
+      v.body,
+    )
+      .into_response(),
+    None => (
+      [
+        (header::CONTENT_TYPE, HeaderValue::from_static("image/svg+xml")),
+        (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+      ],
+      PLACEHOLDER_FAVICON_SVG,
+    )
+      .into_response(),
+  }
 }
 
-pub async fn spawn_with_listener(
-  listener: tokio::net::TcpListener,
-  static_dir: PathBuf,
-  config_path: PathBuf,
-) -> Result<SocketAddr> {
-  let addr = listener.local_addr().context("listener local_addr")?;
-
-  let config_path = Arc::new(config_path);
+async fn fetch_favicon(state: &AppState, entry: &ServerEntry) -> Option<Favicon> {
+  let candidates: &[&str] = match entry.cfg.kind {
+    BackendType::Qbit => &["/favicon.ico", "/images/qbittorrent-tray.svg"],
+    BackendType::Trans => &["/transmission/web/favicon.ico", "/favicon.ico"],
+    BackendType::Command | BackendType::Auto => &[],
+  };
+  let headers = HeaderMap::new();
+  for path in candidates {
+    let uri = Uri::from_static(path);
+    let body = reqwest::Body::from("");
+    let Ok(resp) = forward_once(state, entry, &Method::GET, &uri, &headers, body, None).await else {
+      return None;
+    };
+    if resp.status() != StatusCode::OK {
+      continue;
+    }
+    let content_type = resp
+      .headers()
+      .get(header::CONTENT_TYPE)
+      .filter(|v| v.to_str().is_ok_and(|v| v.starts_with("image/")))
+      .cloned();
+    let Some(content_type) = content_type else {
+      continue;
+    };
+    let Ok(InspectedBody::Complete(body)) = inspect_body(resp, MAX_FAVICON_BYTES).await else {
+      continue;
+    };
+    if body.is_empty() {
+      continue;
+    }
+    return Some(Favicon { content_type, body });
+  }
+  None
+}
 
-  let catalog = Catalog::load(&config_path)?;
-  let catalog = Arc::new(RwLock::new(catalog));
+async fn handle_server_probe(
+  State(state): State<AppState>,
+  AxumPath(id): AxumPath<String>,
+  req: Request<Body>,
+) -> Response {
+  let confirmed = confirms_destructive(req.headers());
+  let limit = state.catalog.read().await.settings.max_upload_bytes();
+  let body = match read_body_bytes(req.into_body(), limit).await {
+    Ok(v) => v,
+    Err(ReadBodyError::TooLarge) => {
+      return (StatusCode::PAYLOAD_TOO_LARGE, "request entity too large").into_response();
+    }
+    Err(_) => {
+      return (StatusCode::BAD_REQUEST, "read body failed").into_response();
+    }
+  };
 
-  let qbit = Arc::new(QbitSessions::new()?);
-  let client = reqwest::Client::builder()
-    .timeout(Duration::from_secs(60))
-    .redirect(Policy::none())
-    .build()
-    .context("build proxy http client")?;
+  let parsed: ProbeRequest = match serde_json::from_slice(&body) {
+    Ok(v) => v,
+    Err(_) => {
+      return (StatusCode::BAD_REQUEST, "invalid json body").into_response();
+    }
+  };
 
-  let state = AppState {
-    catalog,
-    qbit,
-    client,
-    config_path,
+  let entry = {
+    let catalog = state.catalog.read().await;
+    match catalog.servers.get(id.trim()) {
+      Some(v) => v.clone(),
+      None => {
+        return (StatusCode::NOT_FOUND, "unknown server id").into_response();
+      }
+    }
   };
 
-  let index_path = static_dir.join("index.html");
-  let static_service = ServeDir::new(static_dir).fallback(ServeFile::new(index_path));
+  let Ok(method) = Method::from_bytes(parsed.method.trim().to_ascii_uppercase().as_bytes()) else {
+    return (StatusCode::BAD_REQUEST, "invalid method").into_response();
+  };
+  let path = parsed.path.trim();
+  if !path.starts_with('/') {
+    return (StatusCode::BAD_REQUEST, "path must start with '/'").into_response();
+  }
+  let Some(uri) = path.parse::<Uri>().ok().and_then(|uri| normalized_uri(&uri)) else {
+    return (StatusCode::BAD_REQUEST, "invalid path").into_response();
+  };
+  if let Some(resp) = reject_in_maintenance(&state).await {
+    return resp;
+  }
+  if let Some(resp) = reject_by_policy(&entry, uri.path(), confirmed) {
+    return resp;
+  }
 
-  let app = Router::new()
-    .route("/__standalone__/status", get(handle_status))
-    .route("/__standalone__/select", post(handle_select))
-    .route("/__standalone__/config", get(handle_config_get).post(handle_config_update))
-    .route("/api/*path", any(handle_proxy))
-    .route("/transmission/*path", any(handle_proxy))
-    .fallback_service(static_service)
-    .with_state(state);
+  let mut headers = HeaderMap::new();
+  for (name, value) in parsed.headers.iter() {
+    let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value))
+    else {
+      return (StatusCode::BAD_REQUEST, "invalid header").into_response();
+    };
+    headers.insert(name, value);
+  }
+  let body = parsed.body.map(String::into_bytes).unwrap_or_default();
+  if !confirmed && is_destructive_rpc(&entry, &body) {
+    return destructive_not_confirmed();
+  }
 
-  tokio::spawn(async move {
-    if let Err(err) = axum::serve(listener, app.into_make_service()).await {
-      tracing::error!(error = %err, "http server error");
+  let resp = match forward_with_session(&state, &entry, &method, &uri, &headers, None, body).await {
+    Ok(v) => v,
+    Err(err) => {
+      return (StatusCode::BAD_GATEWAY, err.to_string()).into_response();
     }
-  });
-
-  Ok(addr)
-}
+  };
 
-fn normalize_listen_addr(raw: &str) -> Result<SocketAddr> {
-  let raw = raw.trim();
-  if raw.is_empty() {
-    return Err(anyhow!("LISTEN_ADDR is empty"));
+  let status = resp.status().as_u16();
+  let mut out_headers = HashMap::new();
+  for (name, value) in sanitize_response_headers(resp.headers().clone()).iter() {
+    let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+    out_headers
+      .entry(name.as_str().to_string())
+      .and_modify(|v: &mut String| {
+        v.push_str(", ");
+        v.push_str(&value);
+      })
+      .or_insert(value);
   }
 
-  if raw.starts_with(':') {
-    let port: u16 = raw[1..]
-      .parse()
-      .with_context(|| format!("invalid port in LISTEN_ADDR {:?}", raw))?;
-    return Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port));
+  let mut body = Vec::new();
+  let mut truncated = false;
+  let mut stream = resp.bytes_stream();
+  while let Some(next) = stream.next().await {
+    let Ok(chunk) = next else {
+      break;
+    };
+    let room = MAX_PROBE_RESPONSE_BYTES - body.len();
+    if chunk.len() > room {
+      body.extend_from_slice(&chunk[..room]);
+      truncated = true;
+      break;
+    }
+    body.extend_from_slice(&chunk);
   }
 
-  raw
-    .parse::<SocketAddr>()
-    .with_context(|| format!("invalid LISTEN_ADDR {:?}", raw))
+  let out = ProbeResponse {
+    status,
+    headers: out_headers,
+    body: String::from_utf8_lossy(&body).into_owned(),
+    truncated,
+  };
+
+  (
+    [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+    Json(out),
+  )
+    .into_response()
 }
 
-async fn handle_status(
-  State(state): State<AppState>,
-  jar: CookieJar,
-) -> impl IntoResponse {
-  let (selected, items) = {
+async fn handle_config_get(State(state): State<AppState>) -> impl IntoResponse {
+  let (etag, default_server_id, default_qbit_id, default_trans_id, servers, editable) = {
     let catalog = state.catalog.read().await;
-    let selected = catalog.selected_id(&jar).to_string();
-    let mut items = Vec::with_capacity(catalog.order.len());
-    for id in catalog.order.iter() {
-      let entry = catalog.servers.get(id).expect("catalog validated");
-      items.push((
-        entry.cfg.id.clone(),
-        entry.cfg.name.clone(),
-        entry.cfg.kind,
-        entry.cfg.base_url.clone(),
-        entry.base.clone(),
-      ));
+    let etag = config_etag(&catalog.to_config());
+    let default_server_id = catalog.default_id.clone();
+    let mut servers = Vec::with_capacity(catalog.order.len());
+    for entry in catalog.order.iter().filter_map(|id| catalog.servers.get(id)) {
+      if entry.cfg.discovered {
+        continue;
+      }
+      servers.push(ConfigServerPublic {
+        id: entry.cfg.id.clone(),
+        name: entry.cfg.name.clone(),
+        kind: entry.cfg.declared_kind(),
+        base_url: entry.cfg.base_url.clone(),
+        command: entry.cfg.command.clone(),
+        username: entry.cfg.username.clone(),
+        has_password: !entry.cfg.password.is_empty(),
+        auth: entry.cfg.auth,
+        enabled: entry.cfg.enabled,
+        client_cert_path: entry.cfg.client_cert_path.clone(),
+        client_key_path: entry.cfg.client_key_path.clone(),
+        ca_cert_path: entry.cfg.ca_cert_path.clone(),
+        http2: entry.cfg.http2,
+        allowed_paths: entry.cfg.allowed_paths.clone(),
+        denied_paths: entry.cfg.denied_paths.clone(),
+        path_match: entry.cfg.path_match,
+        response_cache: entry.cfg.response_cache.clone(),
+        destructive_paths: entry.cfg.destructive_paths.clone(),
+        destructive_rpc_methods: entry.cfg.destructive_rpc_methods.clone(),
+        rewrite: entry.cfg.rewrite.clone(),
+        passthrough_ui: entry.cfg.passthrough_ui,
+        forward_client_ip: entry.cfg.forward_client_ip,
+        validate_rpc: entry.cfg.validate_rpc,
+        user_agent: entry.cfg.user_agent.clone(),
+        strip_request_headers: entry.cfg.strip_request_headers.clone(),
+        query_params: entry
+          .cfg
+          .query_params
+          .keys()
+          .map(|k| (k.clone(), "***".to_string()))
+          .collect(),
+        force_query_params: entry.cfg.force_query_params,
+        upstream_host: entry.cfg.upstream_host.clone(),
+        max_concurrent_requests: entry.cfg.max_concurrent_requests,
+        max_upload_bytes: entry.cfg.max_upload_bytes,
+        upstream_proxy: entry.cfg.upstream_proxy.as_deref().map(redact_proxy_url),
+        color: entry.cfg.color.clone(),
+        icon: entry.cfg.icon.clone(),
+      });
     }
-    (selected, items)
+    (
+      etag,
+      default_server_id,
+      catalog.default_qbit_id.clone(),
+      catalog.default_trans_id.clone(),
+      servers,
+      catalog.settings.config_editable(),
+    )
   };
-  let deadline = Instant::now() + Duration::from_millis(1200);
-
-  let mut tasks = Vec::with_capacity(items.len());
-  for (id, _name, _kind, _base_url, base) in items.iter() {
-    let id = id.clone();
-    let base = base.clone();
-    tasks.push(async move {
-      let (latency_ms, reachable) = measure_tcp_dial_latency(deadline, &base).await;
-      (id, latency_ms, reachable)
-    });
-  }
-
-  let results = futures_util::future::join_all(tasks).await;
-  let mut lat_map: HashMap<String, (Option<u64>, bool)> = HashMap::with_capacity(results.len());
-  for (id, latency_ms, reachable) in results {
-    lat_map.insert(id, (latency_ms, reachable));
-  }
-
-  let mut servers = Vec::with_capacity(items.len());
-  for (id, name, kind, base_url, _base) in items {
-    let (latency_ms, reachable) = lat_map
-      .get(&id)
-      .cloned()
-      .unwrap_or((None, false));
-    servers.push(ServerPublic {
-      id,
-      name,
-      kind,
-      base_url,
-      latency_ms,
-      reachable,
-    });
-  }
 
-  let out = StatusResponse {
-    schema: 1,
-    selected_id: selected,
+  let out = ConfigResponse {
+    schema: SCHEMA_VERSION,
+    default_server_id,
+    default_qbit_id,
+    default_trans_id,
     servers,
+    config_writable: editable && config_writable(&state.config_path).await,
+    config_editable: editable,
   };
 
   (
-    [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+    [
+      (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+      (header::ETAG, etag),
+    ],
     Json(out),
   )
 }
 
-async fn handle_select(
+async fn handle_config_schema() -> impl IntoResponse {
+  let schema = schemars::schema_for!(ConfigFile);
+  (
+    [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+    Json(schema),
+  )
+}
+
+async fn handle_config_update(
   State(state): State<AppState>,
   req: Request<Body>,
 ) -> Response {
   if req.method() != Method::POST {
     return (StatusCode::METHOD_NOT_ALLOWED, "method not allowed").into_response();
   }
+  if let Some(resp) = config_locked(&state).await {
+    return resp;
+  }
+  if let Some(resp) = require_json(req.headers()) {
+    return resp;
+  }
+  let if_match = req.headers().get(header::IF_MATCH).cloned();
 
-  let body = match read_body_bytes(req.into_body(), 1024).await {
+  let limit = state.catalog.read().await.settings.max_config_body_bytes();
+  let body = match read_body_bytes(req.into_body(), limit).await {
     Ok(v) => v,
+    Err(ReadBodyError::TooLarge) => {
+      return (StatusCode::PAYLOAD_TOO_LARGE, "request entity too large").into_response();
+    }
     Err(_) => {
-      return (StatusCode::BAD_REQUEST, "invalid json body").into_response();
+      return (StatusCode::BAD_REQUEST, "read body failed").into_response();
     }
   };
 
-  let parsed: SelectRequest = match serde_json::from_slice(&body) {
+  let parsed: ConfigUpdateRequest = match serde_json::from_slice(&body) {
     Ok(v) => v,
     Err(_) => {
       return (StatusCode::BAD_REQUEST, "invalid json body").into_response();
     }
   };
 
-  let id = parsed.id.trim().to_string();
-  if id.is_empty() {
-    return (StatusCode::BAD_REQUEST, "id is required").into_response();
-  }
-  {
-    let catalog = state.catalog.read().await;
-    if !catalog.servers.contains_key(&id) {
-      return (StatusCode::BAD_REQUEST, "unknown server id").into_response();
-    }
-  }
-
-  let cookie = format!(
-    "{name}={value}; Path=/; HttpOnly; SameSite=Lax; Max-Age=31536000",
-    name = COOKIE_SELECTED_SERVER,
-    value = id
-  );
-  let mut headers = HeaderMap::new();
-  if let Ok(v) = header::HeaderValue::from_str(&cookie) {
-    headers.insert(header::SET_COOKIE, v);
-  }
-
-  let out = serde_json::json!({ "ok": true, "id": id });
-  (headers, Json(out)).into_response()
+  apply_config_update(&state, parsed, ConfigWrite::Edit(if_match)).await
 }
 
-async fn handle_proxy(
+/// Merges a partial `ConfigUpdateServer` into one stored server; the other
+/// servers are resubmitted as stored, so omitted fields and passwords are kept.
+async fn handle_config_server_patch(
   State(state): State<AppState>,
-  jar: CookieJar,
+  AxumPath(id): AxumPath<String>,
   req: Request<Body>,
 ) -> Response {
-  let entry = {
-    let catalog = state.catalog.read().await;
-    catalog.pick(&jar).clone()
-  };
-
-  let method = req.method().clone();
-  let uri = req.uri().clone();
-  let headers = req.headers().clone();
-
-  let body = match read_body_bytes(req.into_body(), MAX_BODY_BYTES).await {
+  if let Some(resp) = config_locked(&state).await {
+    return resp;
+  }
+  if let Some(resp) = require_json(req.headers()) {
+    return resp;
+  }
+  let if_match = req.headers().get(header::IF_MATCH).cloned();
+  let limit = state.catalog.read().await.settings.max_config_body_bytes();
+  let body = match read_body_bytes(req.into_body(), limit).await {
     Ok(v) => v,
     Err(ReadBodyError::TooLarge) => {
       return (StatusCode::PAYLOAD_TOO_LARGE, "request entity too large").into_response();
@@ -594,107 +5650,124 @@ async fn handle_proxy(
       return (StatusCode::BAD_REQUEST, "read body failed").into_response();
     }
   };
-
-  let mut cookie: Option<String> = None;
-  if entry.cfg.kind == BackendType::Qbit {
-    if let Ok(v) = state.qbit.ensure_cookie(&entry, false).await {
-      cookie = Some(v);
-    }
-  }
-
-  let mut resp = match forward_once(
-    &state,
-    &entry,
-    &method,
-    &uri,
-    &headers,
-    body.clone(),
-    cookie.as_deref(),
-  )
-  .await
-  {
+  let patch: serde_json::Map<String, serde_json::Value> = match serde_json::from_slice(&body) {
     Ok(v) => v,
-    Err(err) => {
-      return (StatusCode::BAD_GATEWAY, err.to_string()).into_response();
+    Err(_) => {
+      return (StatusCode::BAD_REQUEST, "invalid json body").into_response();
     }
   };
+  if patch.get("id").is_some_and(|v| v.as_str() != Some(id.as_str())) {
+    return (StatusCode::BAD_REQUEST, "server.id cannot be changed; use rename").into_response();
+  }
 
-  if entry.cfg.kind == BackendType::Qbit && resp.status() == StatusCode::FORBIDDEN {
-    if let Ok(v) = state.qbit.ensure_cookie(&entry, true).await {
-      cookie = Some(v);
+  let current = state.catalog.read().await.to_config();
+  if !current.servers.iter().any(|s| s.id == id) {
+    return (StatusCode::NOT_FOUND, "unknown server id").into_response();
+  }
+
+  let mut servers = Vec::with_capacity(current.servers.len());
+  for s in &current.servers {
+    let mut fields = serde_json::json!({
+      "id": s.id,
+      "name": s.name,
+      "type": s.kind,
+      "baseUrl": s.base_url,
+      "username": s.username,
+    });
+    if s.id == id {
+      if let Some(fields) = fields.as_object_mut() {
+        fields.extend(patch.clone());
+      }
     }
-    resp = match forward_once(
-      &state,
-      &entry,
-      &method,
-      &uri,
-      &headers,
-      body,
-      cookie.as_deref(),
-    )
-    .await
-    {
-      Ok(v) => v,
+    match serde_json::from_value::<ConfigUpdateServer>(fields) {
+      Ok(v) => servers.push(v),
       Err(err) => {
-        return (StatusCode::BAD_GATEWAY, err.to_string()).into_response();
+        return (StatusCode::BAD_REQUEST, format!("invalid server patch: {err}")).into_response();
       }
-    };
+    }
   }
 
-  let status = resp.status();
-  let mut out_headers = sanitize_response_headers(resp.headers().clone());
+  let parsed = ConfigUpdateRequest {
+    default_server_id: current.default_server_id,
+    default_qbit_id: None,
+    default_trans_id: None,
+    servers,
+  };
+  apply_config_update(&state, parsed, ConfigWrite::Edit(if_match)).await
+}
 
-  let stream = resp
-    .bytes_stream()
-    .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err));
-  let body = Body::from_stream(stream);
+/// Full `standalone.json` as produced by `/config/export`; `password: null`
+/// and `queryParams` values of `***` keep the stored values of a server with
+/// the same id.
+#[derive(Debug, serde::Deserialize)]
+struct ConfigImportRequest {
+  #[serde(flatten)]
+  config: ConfigUpdateRequest,
+  #[serde(flatten)]
+  settings: GatewaySettings,
+}
 
-  let mut out = Response::new(body);
-  *out.status_mut() = status;
-  *out.headers_mut() = std::mem::take(&mut out_headers);
-  out
+#[derive(Debug, Default, serde::Deserialize)]
+struct ConfigExportQuery {
+  #[serde(default)]
+  redact: bool,
 }
 
-async fn handle_config_get(State(state): State<AppState>) -> impl IntoResponse {
-  let (default_server_id, servers) = {
-    let catalog = state.catalog.read().await;
-    let default_server_id = catalog.default_id.clone();
-    let mut servers = Vec::with_capacity(catalog.order.len());
-    for id in catalog.order.iter() {
-      let entry = catalog.servers.get(id).expect("catalog validated");
-      servers.push(ConfigServerPublic {
-        id: entry.cfg.id.clone(),
-        name: entry.cfg.name.clone(),
-        kind: entry.cfg.kind,
-        base_url: entry.cfg.base_url.clone(),
-        username: entry.cfg.username.clone(),
-        has_password: !entry.cfg.password.is_empty(),
-      });
-    }
-    (default_server_id, servers)
-  };
+async fn handle_config_export(
+  State(state): State<AppState>,
+  Query(query): Query<ConfigExportQuery>,
+) -> Response {
+  let config = state.catalog.read().await.to_config();
+  if !query.redact {
+    return (
+      [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+      Json(config),
+    )
+      .into_response();
+  }
 
-  let out = ConfigResponse {
-    schema: 1,
-    default_server_id,
-    servers,
+  let mut out = match serde_json::to_value(&config) {
+    Ok(v) => v,
+    Err(_) => {
+      return (StatusCode::INTERNAL_SERVER_ERROR, "serialize config failed").into_response();
+    }
   };
+  if let Some(proxy) = out.get("upstreamProxy").and_then(|v| v.as_str()) {
+    out["upstreamProxy"] = serde_json::Value::from(redact_proxy_url(proxy));
+  }
+  if let Some(servers) = out.get_mut("servers").and_then(|v| v.as_array_mut()) {
+    for server in servers {
+      server["password"] = serde_json::Value::Null;
+      if let Some(proxy) = server.get("upstreamProxy").and_then(|v| v.as_str()) {
+        server["upstreamProxy"] = serde_json::Value::from(redact_proxy_url(proxy));
+      }
+      if let Some(params) = server.get_mut("queryParams").and_then(|v| v.as_object_mut()) {
+        for value in params.values_mut() {
+          *value = serde_json::Value::from("***");
+        }
+      }
+    }
+  }
 
   (
     [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
     Json(out),
   )
+    .into_response()
 }
 
-async fn handle_config_update(
+async fn handle_config_import(
   State(state): State<AppState>,
   req: Request<Body>,
 ) -> Response {
-  if req.method() != Method::POST {
-    return (StatusCode::METHOD_NOT_ALLOWED, "method not allowed").into_response();
+  if let Some(resp) = config_locked(&state).await {
+    return resp;
   }
-
-  let body = match read_body_bytes(req.into_body(), 64 * 1024).await {
+  if let Some(resp) = require_json(req.headers()) {
+    return resp;
+  }
+  let limit = state.catalog.read().await.settings.max_config_body_bytes();
+  let body = match read_body_bytes(req.into_body(), limit).await {
     Ok(v) => v,
     Err(ReadBodyError::TooLarge) => {
       return (StatusCode::PAYLOAD_TOO_LARGE, "request entity too large").into_response();
@@ -704,20 +5777,67 @@ async fn handle_config_update(
     }
   };
 
-  let parsed: ConfigUpdateRequest = match serde_json::from_slice(&body) {
+  let parsed: ConfigImportRequest = match serde_json::from_slice(&body) {
     Ok(v) => v,
     Err(_) => {
       return (StatusCode::BAD_REQUEST, "invalid json body").into_response();
     }
   };
 
-  let existing_passwords = {
-    let catalog = state.catalog.read().await;
-    catalog
+  apply_config_update(&state, parsed.config, ConfigWrite::Import(Box::new(parsed.settings))).await
+}
+
+/// How [`apply_config_update`] treats the stored config.
+enum ConfigWrite {
+  /// An edit based on the config version named by `If-Match`; settings are
+  /// kept and omitted server fields inherit the stored values.
+  Edit(Option<HeaderValue>),
+  /// A full replacement: the given settings replace the stored ones and
+  /// omitted optional server fields are cleared.
+  Import(Box<GatewaySettings>),
+}
+
+/// Validates and persists a new server list. Passwords and masked
+/// `queryParams` values are inherited for both kinds of [`ConfigWrite`], and
+/// `command` can only be kept or cleared. The catalog stays write-locked from
+/// the version check until the new config is in place.
+async fn apply_config_update(
+  state: &AppState,
+  parsed: ConfigUpdateRequest,
+  write: ConfigWrite,
+) -> Response {
+  let discovered = state.discovered.lock().await.values().cloned().collect::<Vec<_>>();
+  let detected = state.detected_types.lock().await.clone();
+  let mut catalog = state.catalog.write().await;
+  let import = match write {
+    ConfigWrite::Edit(if_match) => {
+      if let Some(resp) = check_config_version(&catalog, if_match.as_ref()) {
+        return resp;
+      }
+      None
+    }
+    ConfigWrite::Import(settings) => Some(*settings),
+  };
+  let importing = import.is_some();
+  let (existing_servers, existing_type_defaults, settings) = {
+    let servers = catalog
       .servers
       .iter()
-      .map(|(id, entry)| (id.clone(), entry.cfg.password.clone()))
-      .collect::<HashMap<String, String>>()
+      .map(|(id, entry)| (id.clone(), entry.cfg.clone()))
+      .collect::<HashMap<String, ServerConfig>>();
+    let type_defaults = match import {
+      Some(_) => (None, None),
+      None => (catalog.default_qbit_id.clone(), catalog.default_trans_id.clone()),
+    };
+    let settings = match import {
+      Some(mut v) => {
+        let stored = catalog.settings.upstream_proxy.as_ref();
+        v.upstream_proxy = unredact_proxy_url(v.upstream_proxy, stored);
+        v
+      }
+      None => catalog.settings.clone(),
+    };
+    (servers, type_defaults, settings)
   };
 
   let mut servers = Vec::with_capacity(parsed.servers.len());
@@ -737,25 +5857,66 @@ async fn handle_config_update(
       name = id.clone();
     }
     let base_url = s.base_url.trim().to_string();
-    if base_url.is_empty() {
+    if base_url.is_empty() && s.kind != BackendType::Command {
       return (StatusCode::BAD_REQUEST, "server.baseUrl is required").into_response();
     }
 
-    if let Ok(base) = Url::parse(&base_url) {
-      if base.scheme().is_empty() || base.host_str().is_none() {
+    if !base_url.is_empty() && parse_unix_base_url(&base_url).is_none() {
+      if let Ok(base) = Url::parse(&base_url) {
+        if base.scheme().is_empty() || base.host_str().is_none() {
+          return (StatusCode::BAD_REQUEST, "server.baseUrl is invalid").into_response();
+        }
+      } else {
         return (StatusCode::BAD_REQUEST, "server.baseUrl is invalid").into_response();
       }
-    } else {
-      return (StatusCode::BAD_REQUEST, "server.baseUrl is invalid").into_response();
     }
 
+    let stored = existing_servers.get(&id);
+    let existing = stored.filter(|_| !importing);
+    // The gateway runs `command`, so only the config file may set it.
+    let command = stored.map(|e| e.command.as_slice()).unwrap_or_default();
+    if s.command.as_ref().is_some_and(|v| !v.is_empty() && v.as_slice() != command) {
+      return (StatusCode::BAD_REQUEST, "server.command can only be set in the config file")
+        .into_response();
+    }
+    let command = match s.command {
+      Some(v) if v.is_empty() => v,
+      _ => command.to_vec(),
+    };
+    let color = merge_optional(s.color, existing.and_then(|e| e.color.as_ref()));
+    if color.as_deref().is_some_and(|v| !is_hex_color(v)) {
+      return (StatusCode::BAD_REQUEST, "server.color is invalid").into_response();
+    }
+    let upstream_proxy = match s.upstream_proxy {
+      Some(v) => unredact_proxy_url(Some(v), stored.and_then(|e| e.upstream_proxy.as_ref())),
+      None => existing.and_then(|e| e.upstream_proxy.clone()),
+    };
     let username = s.username.trim().to_string();
     let password = s
       .password
       .map(|v| v.trim().to_string())
-      .unwrap_or_else(|| existing_passwords.get(&id).cloned().unwrap_or_default());
+      .unwrap_or_else(|| stored.map(|e| e.password.clone()).unwrap_or_default());
+
+    let http2 = match s.http2.as_deref().map(str::trim) {
+      None => existing.and_then(|e| e.http2),
+      Some("") => None,
+      Some(v) => match serde_json::from_value::<Http2Mode>(serde_json::Value::from(v)) {
+        Ok(v) => Some(v),
+        Err(_) => {
+          return (StatusCode::BAD_REQUEST, "server.http2 is invalid").into_response();
+        }
+      },
+    };
 
-    if s.kind == BackendType::Qbit && username.is_empty() && password.is_empty() {
+    let auth = match s.kind {
+      BackendType::Qbit | BackendType::Auto => s.auth.or(existing.and_then(|e| e.auth)),
+      BackendType::Trans | BackendType::Command => None,
+    };
+    if s.kind == BackendType::Qbit
+      && auth != Some(QbitAuth::None)
+      && username.is_empty()
+      && password.is_empty()
+    {
       return (StatusCode::BAD_REQUEST, "qBittorrent server requires username/password").into_response();
     }
 
@@ -764,8 +5925,87 @@ async fn handle_config_update(
       name,
       kind: s.kind,
       base_url,
+      command,
       username,
       password,
+      auth,
+      enabled: s.enabled.or(existing.map(|e| e.enabled)).unwrap_or(true),
+      client_cert_path: merge_optional(
+        s.client_cert_path,
+        existing.and_then(|e| e.client_cert_path.as_ref()),
+      ),
+      client_key_path: merge_optional(
+        s.client_key_path,
+        existing.and_then(|e| e.client_key_path.as_ref()),
+      ),
+      ca_cert_path: merge_optional(s.ca_cert_path, existing.and_then(|e| e.ca_cert_path.as_ref())),
+      http2,
+      allowed_paths: s
+        .allowed_paths
+        .or_else(|| existing.map(|e| e.allowed_paths.clone()))
+        .unwrap_or_default(),
+      denied_paths: s
+        .denied_paths
+        .or_else(|| existing.map(|e| e.denied_paths.clone()))
+        .unwrap_or_default(),
+      path_match: s.path_match.or(existing.and_then(|e| e.path_match)),
+      response_cache: s
+        .response_cache
+        .or_else(|| existing.map(|e| e.response_cache.clone()))
+        .unwrap_or_default(),
+      destructive_paths: s
+        .destructive_paths
+        .or_else(|| existing.map(|e| e.destructive_paths.clone()))
+        .unwrap_or_default(),
+      destructive_rpc_methods: s
+        .destructive_rpc_methods
+        .or_else(|| existing.map(|e| e.destructive_rpc_methods.clone()))
+        .unwrap_or_default(),
+      rewrite: s
+        .rewrite
+        .or_else(|| existing.map(|e| e.rewrite.clone()))
+        .unwrap_or_default(),
+      passthrough_ui: s
+        .passthrough_ui
+        .or(existing.map(|e| e.passthrough_ui))
+        .unwrap_or(false),
+      forward_client_ip: s
+        .forward_client_ip
+        .or(existing.map(|e| e.forward_client_ip))
+        .unwrap_or(false),
+      validate_rpc: s.validate_rpc.or(existing.map(|e| e.validate_rpc)).unwrap_or(false),
+      user_agent: merge_optional(s.user_agent, existing.and_then(|e| e.user_agent.as_ref())),
+      strip_request_headers: s
+        .strip_request_headers
+        .or_else(|| existing.map(|e| e.strip_request_headers.clone()))
+        .unwrap_or_default(),
+      query_params: match s.query_params {
+        Some(v) => unmask_query_params(v, stored.map(|e| &e.query_params)),
+        None => existing.map(|e| e.query_params.clone()).unwrap_or_default(),
+      },
+      force_query_params: s
+        .force_query_params
+        .or(existing.map(|e| e.force_query_params))
+        .unwrap_or(false),
+      upstream_host: merge_optional(
+        s.upstream_host,
+        existing.and_then(|e| e.upstream_host.as_ref()),
+      ),
+      // `0` switches the limit off.
+      max_concurrent_requests: s
+        .max_concurrent_requests
+        .or(existing.and_then(|e| e.max_concurrent_requests))
+        .filter(|v| *v > 0),
+      // `0` falls back to the global limits.
+      max_upload_bytes: s
+        .max_upload_bytes
+        .or(existing.and_then(|e| e.max_upload_bytes))
+        .filter(|v| *v > 0),
+      upstream_proxy,
+      color,
+      icon: merge_optional(s.icon, existing.and_then(|e| e.icon.as_ref())),
+      discovered: false,
+      auto_detected: false,
     });
   }
 
@@ -775,58 +6015,385 @@ async fn handle_config_update(
 
   let mut default_server_id = parsed.default_server_id.trim().to_string();
   if default_server_id.is_empty() {
-    default_server_id = servers[0].id.clone();
-  } else if !servers.iter().any(|s| s.id == default_server_id) {
-    return (StatusCode::BAD_REQUEST, "defaultServerId not found in servers").into_response();
+    let Some(first) = servers.iter().find(|s| s.enabled) else {
+      return (StatusCode::BAD_REQUEST, "all servers are disabled").into_response();
+    };
+    default_server_id = first.id.clone();
+  } else {
+    match servers.iter().find(|s| s.id == default_server_id) {
+      None => {
+        return (StatusCode::BAD_REQUEST, "defaultServerId not found in servers").into_response();
+      }
+      Some(s) if !s.enabled => {
+        return (StatusCode::BAD_REQUEST, "defaultServerId refers to a disabled server")
+          .into_response();
+      }
+      Some(_) => {}
+    }
   }
 
+  // Omitted type defaults are kept while they still point at a server of
+  // the right type; an empty string clears them.
+  let keep_type_default = |update: Option<String>, existing: Option<String>, kind: BackendType| {
+    match update {
+      Some(v) => Some(v.trim().to_string()).filter(|v| !v.is_empty()),
+      None => existing.filter(|id| {
+        servers.iter().any(|s| &s.id == id && (s.kind == kind || s.kind == BackendType::Auto))
+      }),
+    }
+  };
+  let default_qbit_id =
+    keep_type_default(parsed.default_qbit_id, existing_type_defaults.0, BackendType::Qbit);
+  let default_trans_id =
+    keep_type_default(parsed.default_trans_id, existing_type_defaults.1, BackendType::Trans);
+
   let config = ConfigFile {
     default_server_id,
+    default_qbit_id,
+    default_trans_id,
     servers,
+    settings,
   };
 
-  let raw = match serde_json::to_vec_pretty(&config) {
+  let runtime = with_detected(with_discovered(config.clone(), discovered), &detected);
+  let new_catalog = match Catalog::from_config(runtime) {
     Ok(v) => v,
-    Err(_) => {
-      return (StatusCode::INTERNAL_SERVER_ERROR, "serialize config failed").into_response();
+    Err(err) => {
+      return (StatusCode::BAD_REQUEST, format!("config is invalid: {err:#}")).into_response();
+    }
+  };
+
+  if config_layers(&state.config_path).len() > 1 {
+    return layered_config_conflict();
+  }
+  if let Err(err) = write_config_file(&state.config_path, &config).await {
+    return write_config_failed(&err);
+  }
+
+  let old_catalog = std::mem::replace(&mut *catalog, new_catalog);
+  state.qbit.clear_changed(&old_catalog, &catalog).await;
+  drop(catalog);
+  // Base URLs may have changed; status probes inline until the next tick.
+  state.health.write().await.clear();
+  state.favicons.lock().await.clear();
+  state.probe_failures.lock().await.clear();
+  state.detect_failures.lock().await.clear();
+  state.responses.lock().await.clear();
+  state.notify_health_update();
+
+  let detect_state = state.clone();
+  state.spawn_background(async move { resolve_auto_servers(&detect_state, None).await });
+
+  (
+    [(header::ETAG, config_etag(&config))],
+    Json(serde_json::json!({ "ok": true })),
+  )
+    .into_response()
+}
+
+/// Version of the stored config for `ETag` / `If-Match`: a hash of its
+/// serialized bytes.
+fn config_etag(config: &ConfigFile) -> HeaderValue {
+  use std::hash::Hasher;
+
+  let raw = serde_json::to_vec(config).unwrap_or_default();
+  let mut hasher = std::collections::hash_map::DefaultHasher::new();
+  hasher.write(&raw);
+  HeaderValue::from_str(&format!("\"{:016x}\"", hasher.finish())).expect("hex etag")
+}
+
+/// `428` without `If-Match` and `412` when it names another config version,
+/// so concurrent editors don't overwrite each other. Callers hold the catalog
+/// write lock until their edit is stored.
+fn check_config_version(catalog: &Catalog, expected: Option<&HeaderValue>) -> Option<Response> {
+  let Some(expected) = expected else {
+    return Some(
+      (
+        StatusCode::PRECONDITION_REQUIRED,
+        "If-Match is required; load the config first",
+      )
+        .into_response(),
+    );
+  };
+  let current = config_etag(&catalog.to_config());
+  let matches = expected.to_str().is_ok_and(|v| {
+    v.split(',')
+      .map(str::trim)
+      .any(|tag| tag == "*" || tag.as_bytes() == current.as_bytes())
+  });
+  if matches {
+    return None;
+  }
+  Some(
+    (
+      StatusCode::PRECONDITION_FAILED,
+      "config was changed by someone else; reload and retry",
+    )
+      .into_response(),
+  )
+}
+
+/// Resolves `auto` servers whose `baseUrl` has a detected type.
+fn with_detected(mut config: ConfigFile, detected: &HashMap<String, BackendType>) -> ConfigFile {
+  for server in config.servers.iter_mut().filter(|s| s.kind == BackendType::Auto) {
+    if let Some(kind) = detected.get(server.base_url.trim()) {
+      server.kind = *kind;
+      server.auto_detected = true;
+    }
+  }
+  config
+}
+
+/// Appends discovered servers whose ids are not taken by configured ones.
+fn with_discovered(mut config: ConfigFile, discovered: Vec<ServerConfig>) -> ConfigFile {
+  for server in discovered {
+    if config.servers.iter().any(|s| s.id == server.id) {
+      tracing::debug!(server = %server.id, "discovered server id is taken, skipping");
+      continue;
+    }
+    config.servers.push(server);
+  }
+  config
+}
+
+/// A merged config has no single file to write back to.
+fn layered_config_conflict() -> Response {
+  (
+    StatusCode::CONFLICT,
+    "config is layered from multiple files; edit them directly",
+  )
+    .into_response()
+}
+
+/// `415` for config writes that are not `application/json`. Cross-site forms
+/// and `text/plain` fetches cannot send that type without a CORS preflight.
+fn require_json(headers: &HeaderMap) -> Option<Response> {
+  let json = headers
+    .get(header::CONTENT_TYPE)
+    .and_then(|v| v.to_str().ok())
+    .is_some_and(|v| {
+      v.trim_start()
+        .to_ascii_lowercase()
+        .starts_with("application/json")
+    });
+  if json {
+    return None;
+  }
+  Some((StatusCode::UNSUPPORTED_MEDIA_TYPE, "expected application/json").into_response())
+}
+
+/// `403` for config writes when `configEditable` is `false`.
+async fn config_locked(state: &AppState) -> Option<Response> {
+  if state.catalog.read().await.settings.config_editable() {
+    return None;
+  }
+  Some((StatusCode::FORBIDDEN, "config editing is disabled").into_response())
+}
+
+/// Read-only mounts and permission errors answer `409` so the UI can tell that
+/// editing is disabled rather than broken.
+fn write_config_failed(err: &anyhow::Error) -> Response {
+  tracing::error!(error = %format_args!("{err:#}"), "write config failed");
+  let read_only = err.chain().filter_map(|e| e.downcast_ref::<std::io::Error>()).any(|e| {
+    matches!(
+      e.kind(),
+      std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem
+    )
+  });
+  if read_only {
+    return (StatusCode::CONFLICT, "config is read-only; edits disabled").into_response();
+  }
+  (StatusCode::INTERNAL_SERVER_ERROR, "write config failed").into_response()
+}
+
+/// Whether `write_config_file` can succeed, checked by creating a scratch
+/// file next to the config; permission bits alone miss read-only mounts.
+async fn config_writable(path: &Path) -> bool {
+  if config_layers(path).len() > 1 {
+    return false;
+  }
+  let probe = path.with_extension("writable");
+  let opened = tokio::fs::OpenOptions::new()
+    .write(true)
+    .create(true)
+    .truncate(true)
+    .open(&probe)
+    .await;
+  match opened {
+    Ok(_) => {
+      let _ = tokio::fs::remove_file(&probe).await;
+      true
     }
+    Err(_) => false,
+  }
+}
+
+/// `STANDALONE_CONFIG` may list several comma-separated files, layered in
+/// order.
+fn config_layers(path: &Path) -> Vec<PathBuf> {
+  let raw = path.to_string_lossy();
+  if !raw.contains(',') {
+    return vec![path.to_path_buf()];
+  }
+  raw
+    .split(',')
+    .map(str::trim)
+    .filter(|v| !v.is_empty())
+    .map(PathBuf::from)
+    .collect()
+}
+
+/// Applies `overlay` on top of `base`: top-level keys replace, while
+/// `servers` entries are merged field by field into the base entry with the
+/// same `id` (or appended).
+fn merge_config_layer(
+  mut base: serde_json::Value,
+  overlay: serde_json::Value,
+) -> Result<serde_json::Value> {
+  use serde_json::Value;
+
+  let (Value::Object(base_map), Value::Object(overlay_map)) = (&mut base, overlay) else {
+    return Err(anyhow!("config must be a JSON object"));
   };
+  for (key, value) in overlay_map {
+    if key != "servers" {
+      base_map.insert(key, value);
+      continue;
+    }
+    let Value::Array(overlay_servers) = value else {
+      return Err(anyhow!("servers must be an array"));
+    };
+    let servers = base_map.entry("servers").or_insert_with(|| Value::Array(Vec::new()));
+    let Value::Array(servers) = servers else {
+      return Err(anyhow!("servers must be an array"));
+    };
+    for server in overlay_servers {
+      let Value::Object(fields) = server else {
+        return Err(anyhow!("servers entries must be objects"));
+      };
+      let id = fields.get("id").and_then(Value::as_str).map(str::trim).unwrap_or("");
+      let existing = servers.iter_mut().find_map(|s| match s {
+        Value::Object(m) if m.get("id").and_then(Value::as_str).map(str::trim) == Some(id) => {
+          Some(m)
+        }
+        _ => None,
+      });
+      match existing {
+        Some(target) => target.extend(fields),
+        None => servers.push(Value::Object(fields)),
+      }
+    }
+  }
+  Ok(base)
+}
+
+/// Config files may be gzip-compressed, recognized by their magic bytes
+/// rather than the file name.
+fn decompress_config(raw: Vec<u8>) -> std::io::Result<Vec<u8>> {
+  if !raw.starts_with(&[0x1f, 0x8b]) {
+    return Ok(raw);
+  }
+  let mut out = Vec::with_capacity(raw.len() * 4);
+  flate2::read::GzDecoder::new(raw.as_slice()).read_to_end(&mut out)?;
+  Ok(out)
+}
+
+/// Whether `write_config_file` stores `path` gzip-compressed.
+fn is_gzip_path(path: &Path) -> bool {
+  path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gz"))
+}
 
-  if let Some(parent) = state.config_path.parent() {
+/// Atomically replaces the config file via a sibling `.tmp` file.
+async fn write_config_file(path: &Path, config: &ConfigFile) -> Result<()> {
+  let mut raw = serde_json::to_vec_pretty(config).context("serialize config")?;
+  if is_gzip_path(path) {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&raw).context("compress config")?;
+    raw = encoder.finish().context("compress config")?;
+  }
+
+  if let Some(parent) = path.parent() {
     if let Err(err) = tokio::fs::create_dir_all(parent).await {
       tracing::error!(error = %err, "create config dir failed");
     }
   }
 
-  let tmp = state.config_path.with_extension("tmp");
-  if let Err(err) = tokio::fs::write(&tmp, &raw).await {
-    tracing::error!(error = %err, "write config tmp failed");
-    return (StatusCode::INTERNAL_SERVER_ERROR, "write config failed").into_response();
+  let tmp = path.with_extension("tmp");
+  tokio::fs::write(&tmp, &raw)
+    .await
+    .with_context(|| format!("write {}", tmp.display()))?;
+
+  if let Err(err) = tokio::fs::rename(&tmp, path).await {
+    let _ = tokio::fs::remove_file(path).await;
+    tokio::fs::rename(&tmp, path)
+      .await
+      .with_context(|| format!("rename config after {err}"))?;
   }
+  Ok(())
+}
 
-  if let Err(err) = tokio::fs::rename(&tmp, &*state.config_path).await {
-    let _ = tokio::fs::remove_file(&*state.config_path).await;
-    if let Err(err2) = tokio::fs::rename(&tmp, &*state.config_path).await {
-      tracing::error!(error = %err, error2 = %err2, "rename config failed");
-      return (StatusCode::INTERNAL_SERVER_ERROR, "write config failed").into_response();
+/// Forwards a request with the server's qBittorrent session attached,
+/// re-authenticating and retrying once when the backend answers `403`.
+/// `forwarded` holds the headers of a request from a trusted proxy.
+async fn forward_with_session(
+  state: &AppState,
+  entry: &ServerEntry,
+  method: &Method,
+  uri: &Uri,
+  headers: &HeaderMap,
+  forwarded: Option<&HeaderMap>,
+  body: Vec<u8>,
+) -> Result<reqwest::Response> {
+  let mut cookie: Option<String> = None;
+  if entry.cfg.needs_qbit_login() {
+    if let Ok(v) = state.qbit.ensure_cookie(entry, false).await {
+      cookie = Some(v);
     }
   }
 
-  let new_catalog = match Catalog::load(&state.config_path) {
-    Ok(v) => v,
-    Err(err) => {
-      tracing::error!(error = %err, "reload catalog failed");
-      return (StatusCode::BAD_REQUEST, "config is invalid").into_response();
-    }
+  let mut origin = entry.origin.clone();
+  let qbit = QbitHeaders {
+    cookie: cookie.as_deref(),
+    origin: &origin,
   };
+  let mut resp =
+    forward_once_with(state, entry, method, uri, headers, body.clone().into(), qbit).await?;
 
-  {
-    let mut catalog = state.catalog.write().await;
-    *catalog = new_catalog;
+  if entry.cfg.kind == BackendType::Qbit {
+    let rejected;
+    (resp, rejected) = csrf_rejection(resp).await;
+    let retry_origin = csrf_retry_origin(entry, forwarded);
+    if rejected && retry_origin != origin {
+      tracing::info!(
+        server = %entry.cfg.id,
+        origin = %retry_origin,
+        "qB rejected Origin/Referer as cross-site, retrying"
+      );
+      origin = retry_origin;
+      let qbit = QbitHeaders {
+        cookie: cookie.as_deref(),
+        origin: &origin,
+      };
+      resp =
+        forward_once_with(state, entry, method, uri, headers, body.clone().into(), qbit).await?;
+    }
   }
-  state.qbit.clear().await;
 
-  Json(serde_json::json!({ "ok": true })).into_response()
+  if entry.cfg.needs_qbit_login() && resp.status() == StatusCode::FORBIDDEN {
+    state.metrics.record_reauth(&entry.cfg.id, "forbidden");
+    if let Ok(v) = state.qbit.ensure_cookie(entry, true).await {
+      cookie = Some(v);
+    }
+    let qbit = QbitHeaders {
+      cookie: cookie.as_deref(),
+      origin: &origin,
+    };
+    resp = forward_once_with(state, entry, method, uri, headers, body.into(), qbit).await?;
+  }
+  if cookie.is_some() {
+    state.qbit.apply_rotation(&entry.cfg.id, resp.headers()).await;
+  }
+  Ok(resp)
 }
 
 async fn forward_once(
@@ -835,28 +6402,70 @@ async fn forward_once(
   method: &Method,
   uri: &Uri,
   headers: &HeaderMap,
-  body: Vec<u8>,
+  body: reqwest::Body,
   qbit_cookie: Option<&str>,
 ) -> Result<reqwest::Response> {
-  let target = build_target_url(&entry.base, uri)?;
+  let qbit = QbitHeaders {
+    cookie: qbit_cookie,
+    origin: &entry.origin,
+  };
+  forward_once_with(state, entry, method, uri, headers, body, qbit).await
+}
+
+/// Session cookie and `Origin` sent to a qBittorrent server.
+struct QbitHeaders<'a> {
+  cookie: Option<&'a str>,
+  origin: &'a str,
+}
+
+async fn forward_once_with(
+  state: &AppState,
+  entry: &ServerEntry,
+  method: &Method,
+  uri: &Uri,
+  headers: &HeaderMap,
+  body: reqwest::Body,
+  qbit: QbitHeaders<'_>,
+) -> Result<reqwest::Response> {
+  if entry.command.is_some() {
+    return Err(anyhow!("not supported by command backends"));
+  }
+  let mut target = build_target_url(&entry.base, uri)?;
+  apply_query_params(&mut target, &entry.cfg);
   let mut out_headers = sanitize_request_headers(headers.clone());
+  for name in &entry.strip_request_headers {
+    out_headers.remove(name);
+  }
+  // Response rewriting needs plain-text bodies.
+  if !entry.cfg.rewrite.is_empty() {
+    out_headers.remove(header::ACCEPT_ENCODING);
+  }
+  if let Some(v) = &entry.user_agent {
+    out_headers.insert(header::USER_AGENT, v.clone());
+  }
+  if let Some(v) = &entry.host {
+    out_headers.insert(header::HOST, v.clone());
+  }
 
   if entry.cfg.kind == BackendType::Qbit {
-    out_headers.insert("origin", header::HeaderValue::from_str(&entry.origin)?);
+    out_headers.insert("origin", header::HeaderValue::from_str(qbit.origin)?);
     out_headers.insert(
       "referer",
-      header::HeaderValue::from_str(&format!("{}/", entry.origin))?,
+      header::HeaderValue::from_str(&format!("{}/", qbit.origin))?,
     );
-    if let Some(v) = qbit_cookie {
+    if let Some(v) = qbit.cookie {
       out_headers.insert("cookie", header::HeaderValue::from_str(v)?);
     }
   }
 
-  let mut builder = state
-    .client
+  let client = entry.client.as_ref().unwrap_or(&state.client);
+  let mut builder = client
     .request(method.clone(), target)
     .headers(out_headers)
     .body(body);
+  if let Some(timeout) = requested_timeout(headers) {
+    builder = builder.timeout(timeout.min(entry.max_upstream_timeout));
+  }
 
   if entry.cfg.kind == BackendType::Trans
     && (!entry.cfg.username.is_empty() || !entry.cfg.password.is_empty())
@@ -864,7 +6473,131 @@ async fn forward_once(
     builder = builder.basic_auth(entry.cfg.username.clone(), Some(entry.cfg.password.clone()));
   }
 
-  builder.send().await.context("upstream request failed")
+  let start = Instant::now();
+  let resp = builder.send().await;
+  let elapsed = start.elapsed();
+  let status = resp.as_ref().ok().map(|r| r.status());
+  state.metrics.record_request(&entry.cfg.id, status, elapsed);
+  let resp = match resp {
+    Ok(v) => v,
+    Err(err) => return Err(upstream_error(entry, err, "upstream request failed").await),
+  };
+  if entry.slow_request_threshold.is_some_and(|limit| elapsed > limit) {
+    tracing::warn!(
+      server = %entry.cfg.id,
+      method = %method,
+      path = %uri.path(),
+      status = resp.status().as_u16(),
+      duration_ms = elapsed.as_millis() as u64,
+      "slow upstream request"
+    );
+  }
+  Ok(resp)
+}
+
+/// Buffers a short `401`/`403` body to tell qBittorrent's cross-site
+/// rejection apart: it answers a failed Origin/Referer or `Host` check with
+/// the bare body `Unauthorized`, while an expired session gets `Forbidden`.
+/// The response is rebuilt so it can still be relayed.
+async fn csrf_rejection(resp: reqwest::Response) -> (reqwest::Response, bool) {
+  let status = resp.status();
+  let small = resp.content_length().is_some_and(|n| n <= MAX_CSRF_BODY_BYTES);
+  if !matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) || !small {
+    return (resp, false);
+  }
+  let headers = resp.headers().clone();
+  let body = resp.bytes().await.unwrap_or_default();
+  let rejected = body.trim_ascii() == b"Unauthorized";
+  let mut out = axum::http::Response::new(body);
+  *out.status_mut() = status;
+  *out.headers_mut() = headers;
+  (reqwest::Response::from(out), rejected)
+}
+
+/// `Origin` for retrying a request qBittorrent rejected as cross-site. When
+/// its reverse proxy support is on, it checks against the `X-Forwarded-Host`
+/// of the proxy chain rather than the `Host` the gateway sends, so the
+/// origin is rebuilt from the `forwarded` headers of a trusted proxy;
+/// otherwise from `baseUrl` itself.
+fn csrf_retry_origin(entry: &ServerEntry, forwarded: Option<&HeaderMap>) -> String {
+  let first = |name: &str| {
+    forwarded?
+      .get(name)
+      .and_then(|v| v.to_str().ok())
+      .and_then(|v| v.split(',').next())
+      .map(str::trim)
+      .filter(|v| !v.is_empty())
+  };
+  match first("x-forwarded-host") {
+    Some(host) => {
+      let scheme = first("x-forwarded-proto").unwrap_or(entry.base.scheme());
+      format!("{scheme}://{host}")
+    }
+    None => entry.base.origin().ascii_serialization(),
+  }
+}
+
+/// Wraps a failed upstream request. When a plain `http` server fails at the
+/// connection level, checks whether it speaks TLS instead and says so, since
+/// the raw error (reset, malformed response) rarely points at the scheme.
+async fn upstream_error(
+  entry: &ServerEntry,
+  err: reqwest::Error,
+  context: &'static str,
+) -> anyhow::Error {
+  let check_tls = entry.base.scheme() == "http" && entry.socket.is_none() && !err.is_timeout();
+  let err = anyhow!(err).context(context);
+  if check_tls && speaks_tls(&entry.base).await {
+    return err.context(format!(
+      "{} answered with TLS; use an https:// baseUrl",
+      entry.cfg.base_url
+    ));
+  }
+  err
+}
+
+/// Whether the host and port of `base` complete a TLS handshake. Certificates
+/// are not verified; this only tells the protocols apart.
+async fn speaks_tls(base: &Url) -> bool {
+  let mut url = base.clone();
+  let port = base.port_or_known_default();
+  if url.set_scheme("https").is_err() || url.set_port(port).is_err() {
+    return false;
+  }
+  let client = match reqwest::Client::builder()
+    .danger_accept_invalid_certs(true)
+    .no_proxy()
+    .timeout(STATUS_PROBE_TIMEOUT)
+    .build()
+  {
+    Ok(v) => v,
+    Err(_) => return false,
+  };
+  client.head(url).send().await.is_ok()
+}
+
+/// `X-Upstream-Timeout` in whole seconds; zero and unparsable values are
+/// ignored.
+fn requested_timeout(headers: &HeaderMap) -> Option<Duration> {
+  let secs = headers
+    .get(HEADER_UPSTREAM_TIMEOUT)?
+    .to_str()
+    .ok()?
+    .trim()
+    .parse::<u64>()
+    .ok()?;
+  (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Canonical form of a server base URL: no query or fragment, and a path
+/// without trailing slashes (`/` for the root), so that `http://qb:8080/qb`
+/// and `http://qb:8080/qb/` join identically with request paths.
+fn normalize_base_url(mut base: Url) -> Url {
+  let path = base.path().trim_end_matches('/').to_string();
+  base.set_path(if path.is_empty() { "/" } else { &path });
+  base.set_query(None);
+  base.set_fragment(None);
+  base
 }
 
 fn build_target_url(base: &Url, uri: &Uri) -> Result<Url> {
@@ -878,6 +6611,93 @@ fn build_target_url(base: &Url, uri: &Uri) -> Result<Url> {
   Ok(target)
 }
 
+/// Merges the server's `queryParams` into `target`, keeping the client's
+/// parameters as sent. A client parameter with the same name wins unless
+/// `forceQueryParams` is set, in which case it is dropped.
+fn apply_query_params(target: &mut Url, cfg: &ServerConfig) {
+  if cfg.query_params.is_empty() {
+    return;
+  }
+  let key = |pair: &str| pair.split('=').next().unwrap_or("").to_string();
+  let force = cfg.force_query_params;
+  let client = target.query().unwrap_or("").split('&').filter(|v| !v.is_empty());
+  let client = client.collect::<Vec<_>>();
+  let mut parts = client
+    .iter()
+    .filter(|v| !(force && cfg.query_params.contains_key(&key(v))))
+    .map(|v| v.to_string())
+    .collect::<Vec<_>>();
+  for (k, v) in &cfg.query_params {
+    if force || !client.iter().any(|p| key(p) == *k) {
+      parts.push(format!("{k}={v}"));
+    }
+  }
+  target.set_query(Some(&parts.join("&")));
+}
+
+/// A request path as the backend resolves it: percent-encoded unreserved
+/// characters decoded, repeated slashes collapsed and `.`/`..` segments
+/// removed. Path policies are matched against this form, and it is what gets
+/// forwarded, so `/api//v2/app/%73hutdown` cannot slip past `deniedPaths`.
+fn normalize_path(path: &str) -> String {
+  let bytes = path.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let byte = (bytes[i] == b'%')
+      .then(|| bytes.get(i + 1..i + 3))
+      .flatten()
+      .and_then(|h| std::str::from_utf8(h).ok())
+      .filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()))
+      .and_then(|h| u8::from_str_radix(h, 16).ok())
+      .filter(|b| b.is_ascii_alphanumeric() || b"-._~".contains(b));
+    match byte {
+      Some(b) => {
+        decoded.push(b);
+        i += 3;
+      }
+      None => {
+        decoded.push(bytes[i]);
+        i += 1;
+      }
+    }
+  }
+  let decoded = String::from_utf8_lossy(&decoded);
+
+  let mut segments = Vec::new();
+  for segment in decoded.split('/') {
+    match segment {
+      "" | "." => {}
+      ".." => {
+        segments.pop();
+      }
+      v => segments.push(v),
+    }
+  }
+  let mut out = format!("/{}", segments.join("/"));
+  let dir = decoded.ends_with('/') || decoded.ends_with("/.") || decoded.ends_with("/..");
+  if dir && !segments.is_empty() {
+    out.push('/');
+  }
+  out
+}
+
+/// `uri` with its path replaced by [`normalize_path`]; `None` if the result
+/// is not a valid URI.
+fn normalized_uri(uri: &Uri) -> Option<Uri> {
+  let path = normalize_path(uri.path());
+  if path == uri.path() {
+    return Some(uri.clone());
+  }
+  let mut parts = uri.clone().into_parts();
+  let path_and_query = match uri.query() {
+    Some(q) => format!("{path}?{q}"),
+    None => path,
+  };
+  parts.path_and_query = Some(path_and_query.parse().ok()?);
+  Uri::from_parts(parts).ok()
+}
+
 fn join_path(a: &str, b: &str) -> String {
   let aslash = a.ends_with('/');
   let bslash = b.starts_with('/');
@@ -903,12 +6723,345 @@ fn join_url(base: &Url, suffix: &str) -> Result<Url> {
   Ok(out)
 }
 
+async fn run_session_sweeper(state: AppState, idle: Duration) {
+  let period = (idle / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+  let mut ticker = tokio::time::interval(period);
+  ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+  loop {
+    ticker.tick().await;
+    state.qbit.expire_idle(&state.catalog, idle).await;
+  }
+}
+
+/// Browses for `_http._tcp` services and keeps the instances matching an
+/// `mdnsDiscovery` rule in the catalog until they disappear.
+#[cfg(feature = "mdns")]
+async fn run_mdns_discovery(state: AppState, rules: Vec<MdnsRule>) {
+  use mdns_sd::{ServiceDaemon, ServiceEvent};
+
+  let matchers = rules
+    .iter()
+    .map(|rule| rule.matcher().expect("validated"))
+    .collect::<Vec<_>>();
+  // The daemon stops browsing when dropped.
+  let (_daemon, events) = match ServiceDaemon::new().and_then(|d| {
+    let events = d.browse("_http._tcp.local.")?;
+    Ok((d, events))
+  }) {
+    Ok(v) => v,
+    Err(err) => {
+      tracing::error!(error = %err, "mDNS discovery failed to start");
+      return;
+    }
+  };
+
+  while let Ok(event) = events.recv_async().await {
+    let changed = match event {
+      ServiceEvent::ServiceResolved(info) => {
+        let instance = info
+          .get_fullname()
+          .strip_suffix(info.get_type())
+          .unwrap_or(info.get_fullname())
+          .trim_end_matches('.');
+        let Some(rule) = rules
+          .iter()
+          .zip(&matchers)
+          .find(|(_, m)| m.is_match(instance))
+          .map(|(rule, _)| rule)
+        else {
+          continue;
+        };
+        let addrs = info.get_addresses();
+        let Some(addr) = addrs.iter().find(|a| a.is_ipv4()).or(addrs.iter().next()) else {
+          continue;
+        };
+        let server = serde_json::json!({
+          "id": discovered_server_id(instance),
+          "name": instance,
+          "type": rule.kind,
+          "baseUrl": format!("http://{}", format_host_port(&addr.to_string(), info.get_port())),
+          "username": rule.username,
+          "password": rule.password,
+        });
+        let Ok(mut server) = serde_json::from_value::<ServerConfig>(server) else {
+          continue;
+        };
+        server.discovered = true;
+        let mut discovered = state.discovered.lock().await;
+        let known = discovered
+          .get(info.get_fullname())
+          .is_some_and(|old| old.base_url == server.base_url && old.kind == server.kind);
+        if !known {
+          tracing::info!(server = %server.id, base_url = %server.base_url, "discovered server");
+          discovered.insert(info.get_fullname().to_string(), server);
+        }
+        !known
+      }
+      ServiceEvent::ServiceRemoved(_, fullname) => {
+        let removed = state.discovered.lock().await.remove(&fullname);
+        if let Some(server) = &removed {
+          tracing::info!(server = %server.id, "discovered server went away");
+        }
+        removed.is_some()
+      }
+      _ => false,
+    };
+    if changed {
+      refresh_discovered(&state).await;
+    }
+  }
+  tracing::warn!("mDNS discovery stopped");
+}
+
+/// `mdns-` followed by the instance name folded to `[a-z0-9-]`.
+#[cfg(feature = "mdns")]
+fn discovered_server_id(instance: &str) -> String {
+  let slug = instance
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+    .collect::<String>();
+  format!("mdns-{}", slug.trim_matches('-'))
+}
+
+/// Rebuilds the catalog from the configured servers plus the current
+/// discoveries.
+#[cfg(feature = "mdns")]
+async fn refresh_discovered(state: &AppState) {
+  let discovered = state.discovered.lock().await.values().cloned().collect::<Vec<_>>();
+  let detected = state.detected_types.lock().await.clone();
+  let old_catalog = {
+    let mut catalog = state.catalog.write().await;
+    let config = with_detected(with_discovered(catalog.to_config(), discovered), &detected);
+    match Catalog::from_config(config) {
+      Ok(v) => std::mem::replace(&mut *catalog, v),
+      Err(err) => {
+        tracing::warn!(error = %format_args!("{err:#}"), "discovered servers rejected");
+        return;
+      }
+    }
+  };
+  let catalog = state.catalog.read().await;
+  state.qbit.clear_changed(&old_catalog, &catalog).await;
+  drop(catalog);
+  state.notify_health_update();
+}
+
+/// Detects the type of `auto` servers (all, or just `only`) that have no
+/// cached result and rebuilds the catalog with what is known. Probes run
+/// without any lock held; a failed detection is not retried for
+/// [`DETECT_RETRY_AFTER`].
+async fn resolve_auto_servers(state: &AppState, only: Option<&str>) {
+  let pending = {
+    let catalog = state.catalog.read().await;
+    catalog
+      .order
+      .iter()
+      .filter(|id| only.is_none_or(|only| only == id.as_str()))
+      .filter_map(|id| catalog.servers.get(id))
+      .filter(|entry| entry.cfg.kind == BackendType::Auto)
+      .cloned()
+      .collect::<Vec<_>>()
+  };
+  if pending.is_empty() {
+    return;
+  }
+
+  let known = state.detected_types.lock().await.clone();
+  let tasks = {
+    let failures = state.detect_failures.lock().await;
+    pending
+      .iter()
+      .filter(|entry| !known.contains_key(&entry.cfg.base_url))
+      .filter(|entry| {
+        failures.get(&entry.cfg.base_url).is_none_or(|at| at.elapsed() >= DETECT_RETRY_AFTER)
+      })
+      .map(|entry| async move { (entry, detect_backend_type(state, entry).await) })
+      .collect::<Vec<_>>()
+  };
+  let results = futures_util::future::join_all(tasks).await;
+
+  let detected = {
+    let mut detected = state.detected_types.lock().await;
+    let mut failures = state.detect_failures.lock().await;
+    for (entry, kind) in results {
+      let base_url = &entry.cfg.base_url;
+      match kind {
+        Some(kind) => {
+          tracing::info!(server = %entry.cfg.id, kind = kind.label(), "detected backend type");
+          failures.remove(base_url);
+          detected.insert(base_url.clone(), kind);
+        }
+        None => {
+          tracing::warn!(server = %entry.cfg.id, base_url = %base_url, "backend type not detected");
+          failures.insert(base_url.clone(), Instant::now());
+        }
+      }
+    }
+    if !pending.iter().any(|entry| detected.contains_key(&entry.cfg.base_url)) {
+      return;
+    }
+    detected.clone()
+  };
+
+  let discovered = state.discovered.lock().await.values().cloned().collect::<Vec<_>>();
+  let old_catalog = {
+    let mut catalog = state.catalog.write().await;
+    let config = with_detected(with_discovered(catalog.to_config(), discovered), &detected);
+    match Catalog::from_config(config) {
+      Ok(v) => std::mem::replace(&mut *catalog, v),
+      Err(err) => {
+        tracing::warn!(error = %format_args!("{err:#}"), "detected backend types rejected");
+        return;
+      }
+    }
+  };
+  let catalog = state.catalog.read().await;
+  state.qbit.clear_changed(&old_catalog, &catalog).await;
+  drop(catalog);
+  state.notify_health_update();
+}
+
+/// Tells the backends apart by their unauthenticated answers: Transmission
+/// challenges an RPC call without a session id with `409` (or `401` from its
+/// own Basic auth), qBittorrent answers `/api/v2/app/version` with `200`, or
+/// `403` until logged in.
+async fn detect_backend_type(state: &AppState, entry: &ServerEntry) -> Option<BackendType> {
+  let deadline = Instant::now() + QBIT_LOGIN_TIMEOUT;
+  let probe = |method: Method, path: &'static str| async move {
+    let uri = Uri::from_static(path);
+    let headers = HeaderMap::new();
+    let fut = forward_once(state, entry, &method, &uri, &headers, reqwest::Body::from(""), None);
+    timeout_at(deadline, fut).await.ok()?.ok()
+  };
+
+  if let Some(resp) = probe(Method::POST, "/transmission/rpc").await {
+    let realm = resp
+      .headers()
+      .get(header::WWW_AUTHENTICATE)
+      .and_then(|v| v.to_str().ok())
+      .unwrap_or("");
+    match resp.status() {
+      StatusCode::CONFLICT if resp.headers().contains_key(TRANSMISSION_SESSION_HEADER) => {
+        return Some(BackendType::Trans);
+      }
+      StatusCode::UNAUTHORIZED if realm.contains("Transmission") => {
+        return Some(BackendType::Trans);
+      }
+      _ => {}
+    }
+  }
+  let resp = probe(Method::GET, "/api/v2/app/version").await?;
+  matches!(resp.status(), StatusCode::OK | StatusCode::FORBIDDEN).then_some(BackendType::Qbit)
+}
+
+/// Probes every enabled server each `interval` and keeps `AppState::health`
+/// current, logging reachability transitions.
+async fn run_health_monitor(state: AppState, interval: Duration) {
+  let mut ticker = tokio::time::interval(interval);
+  ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+  loop {
+    ticker.tick().await;
+
+    let (items, probe_timeout, retries, threshold, concurrency) = {
+      let catalog = state.catalog.read().await;
+      let items = catalog
+        .order
+        .iter()
+        .filter_map(|id| catalog.servers.get(id))
+        .filter(|entry| entry.cfg.enabled)
+        .cloned()
+        .collect::<Vec<_>>();
+      let settings = &catalog.settings;
+      (
+        items,
+        settings.status_probe_timeout(),
+        settings.status_connect_retries.unwrap_or(0),
+        settings.reachable_failure_threshold(),
+        settings.status_concurrency(),
+      )
+    };
+
+    let deadline = Instant::now() + probe_timeout;
+    let state = &state;
+    let tasks = items
+      .iter()
+      .map(|entry| async move {
+        let (latency_ms, reachable) = measure_dial_latency(deadline, retries, entry).await;
+        let reachable = state.debounce_reachable(&entry.cfg.id, reachable, threshold).await;
+        state.metrics.record_probe(&entry.cfg.id, latency_ms, reachable);
+        (entry, Health { latency_ms, reachable })
+      })
+      .collect::<Vec<_>>();
+    let results = futures_util::stream::iter(tasks)
+      .buffer_unordered(concurrency)
+      .collect::<Vec<_>>()
+      .await;
+
+    let mut health = state.health.write().await;
+    let mut next = HashMap::with_capacity(results.len());
+    for (entry, h) in results {
+      let id = &entry.cfg.id;
+      match health.get(id) {
+        Some(prev) if prev.reachable && !h.reachable => {
+          tracing::warn!(server = %id, base_url = %entry.cfg.base_url, "server went down");
+        }
+        Some(prev) if !prev.reachable && h.reachable => {
+          tracing::warn!(server = %id, base_url = %entry.cfg.base_url, "server is back up");
+        }
+        _ => {}
+      }
+      next.insert(id.clone(), h);
+    }
+    *health = next;
+    drop(health);
+    state.notify_health_update();
+  }
+}
+
+/// Probes `entry`, making up to `retries` further attempts after a failure
+/// as long as `deadline` leaves room. The latency is that of the successful
+/// attempt.
+async fn measure_dial_latency(
+  deadline: Instant,
+  retries: u32,
+  entry: &ServerEntry,
+) -> (Option<u64>, bool) {
+  let mut attempts = 1;
+  loop {
+    let result = dial_once(deadline, entry).await;
+    if result.1 || attempts > retries || Instant::now() + STATUS_RETRY_DELAY >= deadline {
+      if result.1 && attempts > 1 {
+        tracing::debug!(server = %entry.cfg.id, attempts, "probe succeeded after retrying");
+      }
+      return result;
+    }
+    attempts += 1;
+    tokio::time::sleep(STATUS_RETRY_DELAY).await;
+  }
+}
+
+async fn dial_once(deadline: Instant, entry: &ServerEntry) -> (Option<u64>, bool) {
+  if let Some(pool) = &entry.command {
+    let start = Instant::now();
+    return match timeout_at(deadline, pool.ping()).await {
+      Ok(Ok(())) => (Some(start.elapsed().as_millis() as u64), true),
+      _ => (None, false),
+    };
+  }
+  match (&entry.socket, &entry.proxy) {
+    (Some(path), _) => measure_unix_dial_latency(deadline, path).await,
+    (None, Some(proxy)) => measure_tcp_dial_latency(deadline, proxy).await,
+    (None, None) => measure_tcp_dial_latency(deadline, &entry.base).await,
+  }
+}
+
 async fn measure_tcp_dial_latency(deadline: Instant, base: &Url) -> (Option<u64>, bool) {
   let Some(host) = base.host_str() else {
     return (None, false);
   };
 
-  let port = base.port_or_known_default().unwrap_or(80);
+  let default_port = if base.scheme().starts_with("socks5") { 1080 } else { 80 };
+  let port = base.port_or_known_default().unwrap_or(default_port);
   let addr = format_host_port(host, port);
 
   let start = Instant::now();
@@ -923,6 +7076,263 @@ async fn measure_tcp_dial_latency(deadline: Instant, base: &Url) -> (Option<u64>
   }
 }
 
+async fn measure_unix_dial_latency(deadline: Instant, path: &Path) -> (Option<u64>, bool) {
+  #[cfg(unix)]
+  {
+    let start = Instant::now();
+    match timeout_at(deadline, tokio::net::UnixStream::connect(path)).await {
+      Ok(Ok(stream)) => {
+        drop(stream);
+        let ms = start.elapsed().as_millis() as u64;
+        (Some(ms), true)
+      }
+      _ => (None, false),
+    }
+  }
+  #[cfg(not(unix))]
+  {
+    let _ = (deadline, path);
+    (None, false)
+  }
+}
+
+/// Splits `unix:///run/transmission.sock[:/prefix]` into the socket path and
+/// the base path, which plays the same role as the path of an HTTP baseUrl.
+fn parse_unix_base_url(raw: &str) -> Option<(PathBuf, String)> {
+  let rest = raw.strip_prefix("unix://")?;
+  let (socket, path) = match rest.rsplit_once(':') {
+    Some((socket, path)) if path.starts_with('/') => (socket, path),
+    _ => (rest, ""),
+  };
+  if socket.is_empty() {
+    return None;
+  }
+  Some((PathBuf::from(socket), path.to_string()))
+}
+
+fn build_server_client(
+  cfg: &ServerConfig,
+  socket: Option<&Path>,
+  proxy: Option<&Url>,
+  min_tls: Option<reqwest::tls::Version>,
+) -> Result<Option<reqwest::Client>> {
+  if socket.is_none()
+    && cfg.client_cert_path.is_none()
+    && cfg.client_key_path.is_none()
+    && cfg.ca_cert_path.is_none()
+    && cfg.upstream_proxy.is_none()
+    && cfg.http2.is_none()
+  {
+    return Ok(None);
+  }
+
+  let mut builder = with_min_tls(reqwest::Client::builder(), min_tls)
+    .timeout(PROXY_TIMEOUT)
+    .redirect(Policy::none());
+  builder = match cfg.http2 {
+    None => builder.http1_only(),
+    Some(Http2Mode::Alpn) => builder,
+    Some(Http2Mode::PriorKnowledge) => builder.http2_prior_knowledge(),
+  };
+  if proxy.is_some() {
+    builder = with_upstream_proxy(builder, proxy)?;
+  } else if cfg.upstream_proxy.is_some() {
+    builder = builder.no_proxy();
+  }
+
+  if let Some(path) = socket {
+    builder = with_unix_socket(builder, path)?;
+  }
+
+  match (&cfg.client_cert_path, &cfg.client_key_path) {
+    (Some(cert_path), key_path) => {
+      let mut pem = std::fs::read(cert_path)
+        .with_context(|| format!("read clientCertPath {:?}", cert_path))?;
+      if let Some(key_path) = key_path {
+        let key = std::fs::read(key_path)
+          .with_context(|| format!("read clientKeyPath {:?}", key_path))?;
+        pem.push(b'\n');
+        pem.extend_from_slice(&key);
+      }
+      let identity = reqwest::Identity::from_pem(&pem)
+        .context("parse client certificate/key (expected PEM)")?;
+      builder = builder.identity(identity);
+    }
+    (None, Some(_)) => {
+      return Err(anyhow!("clientKeyPath requires clientCertPath"));
+    }
+    (None, None) => {}
+  }
+
+  if let Some(ca_path) = &cfg.ca_cert_path {
+    let pem = std::fs::read(ca_path).with_context(|| format!("read caCertPath {:?}", ca_path))?;
+    let certs = reqwest::Certificate::from_pem_bundle(&pem)
+      .context("parse CA certificates (expected PEM)")?;
+    if certs.is_empty() {
+      return Err(anyhow!("caCertPath {:?} contains no certificates", ca_path));
+    }
+    for cert in certs {
+      builder = builder.add_root_certificate(cert);
+    }
+  }
+
+  builder.build().map(Some).context("build http client")
+}
+
+fn with_min_tls(
+  builder: reqwest::ClientBuilder,
+  version: Option<reqwest::tls::Version>,
+) -> reqwest::ClientBuilder {
+  match version {
+    Some(v) => builder.min_tls_version(v),
+    None => builder,
+  }
+}
+
+fn parse_tls_version(v: &str) -> Result<reqwest::tls::Version> {
+  match v.trim() {
+    "1.0" => Ok(reqwest::tls::Version::TLS_1_0),
+    "1.1" => Ok(reqwest::tls::Version::TLS_1_1),
+    "1.2" => Ok(reqwest::tls::Version::TLS_1_2),
+    "1.3" => Ok(reqwest::tls::Version::TLS_1_3),
+    _ => Err(anyhow!(
+      "minTlsVersion {:?} is invalid (expected \"1.0\", \"1.1\", \"1.2\" or \"1.3\")",
+      v
+    )),
+  }
+}
+
+fn with_upstream_proxy(
+  builder: reqwest::ClientBuilder,
+  proxy: Option<&Url>,
+) -> Result<reqwest::ClientBuilder> {
+  let Some(proxy) = proxy else {
+    return Ok(builder);
+  };
+  let proxy = reqwest::Proxy::all(proxy.as_str()).context("invalid upstream proxy")?;
+  Ok(builder.proxy(proxy))
+}
+
+/// Accepts `http://`, `https://`, `socks5://` and `socks5h://` proxy URLs,
+/// optionally with `user:password@` credentials.
+fn parse_proxy_url(v: &str) -> Result<Url> {
+  let url = Url::parse(v.trim()).map_err(|_| anyhow!("invalid proxy URL"))?;
+  if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+    return Err(anyhow!("unsupported proxy scheme {:?}", url.scheme()));
+  }
+  if url.host_str().is_none() {
+    return Err(anyhow!("proxy URL has no host"));
+  }
+  Ok(url)
+}
+
+/// The config API and redacted exports return proxy URLs without
+/// credentials; sending that form back keeps the stored credentials. Blank
+/// values clear the proxy.
+fn unredact_proxy_url(update: Option<String>, stored: Option<&String>) -> Option<String> {
+  let v = update?.trim().to_string();
+  match stored {
+    _ if v.is_empty() => None,
+    Some(old) if v == redact_proxy_url(old) => Some(old.clone()),
+    _ => Some(v),
+  }
+}
+
+fn redact_proxy_url(v: &str) -> String {
+  match Url::parse(v) {
+    Ok(mut url) if !url.username().is_empty() || url.password().is_some() => {
+      let _ = url.set_username("");
+      let _ = url.set_password(None);
+      url.to_string()
+    }
+    _ => v.to_string(),
+  }
+}
+
+#[cfg(unix)]
+fn with_unix_socket(builder: reqwest::ClientBuilder, path: &Path) -> Result<reqwest::ClientBuilder> {
+  Ok(builder.unix_socket(path))
+}
+
+#[cfg(not(unix))]
+fn with_unix_socket(_builder: reqwest::ClientBuilder, _path: &Path) -> Result<reqwest::ClientBuilder> {
+  Err(anyhow!("unix socket baseUrl is not supported on this platform"))
+}
+
+/// Accepts a bare `host[:port]` authority (no userinfo) as a `Host` value.
+fn parse_upstream_host(v: &str) -> Result<HeaderValue> {
+  let authority = v.parse::<axum::http::uri::Authority>()?;
+  if authority.as_str().contains('@') || authority.host().is_empty() {
+    return Err(anyhow!("expected host[:port]"));
+  }
+  Ok(HeaderValue::from_str(authority.as_str())?)
+}
+
+/// Dot-separated hostname labels, optionally with a leading `.`.
+fn is_plausible_domain(v: &str) -> bool {
+  let v = v.strip_prefix('.').unwrap_or(v);
+  !v.is_empty()
+    && v.len() <= 253
+    && v.split('.').all(|label| {
+      !label.is_empty()
+        && label.len() <= 63
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+/// Unreserved URL characters, which need no percent-encoding anywhere in a
+/// query string.
+fn is_url_safe(v: &str) -> bool {
+  v.bytes().all(|b| b.is_ascii_alphanumeric() || b"-._~".contains(&b))
+}
+
+fn is_hex_color(v: &str) -> bool {
+  let Some(hex) = v.strip_prefix('#') else {
+    return false;
+  };
+  matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn trim_optional(v: &mut Option<String>) {
+  *v = v
+    .take()
+    .map(|s| s.trim().to_string())
+    .filter(|s| !s.is_empty());
+}
+
+/// Optional per-server fields keep their stored value when an update omits
+/// them, mirroring the password semantics; an empty string clears them.
+fn merge_optional(update: Option<String>, existing: Option<&String>) -> Option<String> {
+  match update {
+    Some(v) => {
+      let v = v.trim();
+      (!v.is_empty()).then(|| v.to_string())
+    }
+    None => existing.cloned(),
+  }
+}
+
+/// Like passwords, `queryParams` values come back from the config API and
+/// redacted exports as `***`; sending that back keeps the stored value for
+/// the key.
+fn unmask_query_params(
+  update: BTreeMap<String, String>,
+  stored: Option<&BTreeMap<String, String>>,
+) -> BTreeMap<String, String> {
+  update
+    .into_iter()
+    .map(|(k, v)| {
+      let v = match stored.and_then(|e| e.get(&k)) {
+        Some(old) if v == "***" => old.clone(),
+        _ => v,
+      };
+      (k, v)
+    })
+    .collect()
+}
+
 fn format_host_port(host: &str, port: u16) -> String {
   if host.contains(':') && !host.starts_with('[') {
     format!("[{host}]:{port}")
@@ -939,26 +7349,140 @@ fn format_host_only(host: &str) -> String {
   }
 }
 
-fn extract_set_cookie_pairs(headers: &HeaderMap) -> Vec<String> {
-  let mut out = Vec::new();
-  for value in headers.get_all(header::SET_COOKIE).iter() {
+/// `name=value` pairs from `Set-Cookie` headers, plus the number of headers
+/// seen and why any of them were skipped.
+#[derive(Debug, Default)]
+struct SetCookiePairs {
+  pairs: Vec<String>,
+  seen: usize,
+  skipped: Vec<String>,
+}
+
+impl SetCookiePairs {
+  /// Explains an empty result, e.g. when a fronting proxy rewrote cookies.
+  fn describe_empty(&self) -> String {
+    if self.seen == 0 {
+      return "no Set-Cookie header in response".to_string();
+    }
+    format!(
+      "{} Set-Cookie header(s) seen, all skipped: {}",
+      self.seen,
+      self.skipped.join(", ")
+    )
+  }
+}
+
+fn extract_set_cookie_pairs(headers: &HeaderMap) -> SetCookiePairs {
+  let mut out = SetCookiePairs::default();
+  for (i, value) in headers.get_all(header::SET_COOKIE).iter().enumerate() {
+    out.seen += 1;
+    let n = i + 1;
     let Ok(raw) = value.to_str() else {
+      out.skipped.push(format!("#{n} is not valid UTF-8"));
       continue;
     };
-    let Some(first) = raw.split(';').next() else {
-      continue;
-    };
-    let pair = first.trim();
+    let pair = raw.split(';').next().unwrap_or("").trim();
     if pair.is_empty() {
+      out.skipped.push(format!("#{n} is empty"));
       continue;
     }
     let mut parts = pair.splitn(2, '=');
     let name = parts.next().unwrap_or("").trim();
     let value = parts.next().unwrap_or("").trim();
     if name.is_empty() {
+      out.skipped.push(format!("#{n} has an empty cookie name"));
       continue;
     }
-    out.push(format!("{name}={value}"));
+    out.pairs.push(format!("{name}={value}"));
+  }
+  out
+}
+
+/// Rewrites an upstream `Location` that points back at the backend's own
+/// origin (or base path) into a gateway-relative path, so redirects keep the
+/// browser on the gateway. Redirects to other origins are left untouched.
+fn rewrite_location_header(entry: &ServerEntry, headers: &mut HeaderMap) {
+  let Some(location) = headers.get(header::LOCATION).and_then(|v| v.to_str().ok()) else {
+    return;
+  };
+
+  let rewritten = match Url::parse(location) {
+    Ok(url) => {
+      if !url.origin().ascii_serialization().eq_ignore_ascii_case(&entry.origin) {
+        return;
+      }
+      let mut out = strip_base_path(&entry.base, url.path());
+      if let Some(q) = url.query() {
+        out.push('?');
+        out.push_str(q);
+      }
+      if let Some(f) = url.fragment() {
+        out.push('#');
+        out.push_str(f);
+      }
+      out
+    }
+    Err(_) if location.starts_with('/') && !location.starts_with("//") => {
+      let (path, rest) = match location.find(['?', '#']) {
+        Some(i) => location.split_at(i),
+        None => (location, ""),
+      };
+      format!("{}{}", strip_base_path(&entry.base, path), rest)
+    }
+    Err(_) => return,
+  };
+
+  if let Ok(v) = HeaderValue::from_str(&rewritten) {
+    headers.insert(header::LOCATION, v);
+  }
+}
+
+fn strip_base_path(base: &Url, path: &str) -> String {
+  let base_path = base.path().trim_end_matches('/');
+  if base_path.is_empty() {
+    return path.to_string();
+  }
+  match path.strip_prefix(base_path) {
+    Some("") => "/".to_string(),
+    Some(rest) if rest.starts_with('/') => rest.to_string(),
+    _ => path.to_string(),
+  }
+}
+
+/// Points `index.html` at `base_path` (in `/prefix/` form): root-absolute
+/// `src`/`href` attributes gain the prefix and `<base href>` is set or
+/// inserted so relative asset URLs resolve under the subpath.
+fn rewrite_index_html(html: &str, base_path: &str) -> String {
+  let mut out = String::with_capacity(html.len() + 64);
+  let mut rest = html;
+  loop {
+    let lower = rest.to_ascii_lowercase();
+    let next = ["src=\"/", "href=\"/", "src='/", "href='/"]
+      .iter()
+      .filter_map(|pat| lower.find(pat).map(|i| (i, pat.len())))
+      .min();
+    let Some((i, len)) = next else {
+      out.push_str(rest);
+      break;
+    };
+    let (head, tail) = rest.split_at(i + len);
+    out.push_str(&head[..head.len() - 1]);
+    if tail.starts_with('/') || tail.starts_with(base_path.trim_start_matches('/')) {
+      out.push('/');
+    } else {
+      out.push_str(base_path);
+    }
+    rest = tail;
+  }
+
+  let lower = out.to_ascii_lowercase();
+  let base_tag = format!("<base href=\"{base_path}\">");
+  if let Some(start) = lower.find("<base") {
+    let end = lower[start..].find('>').map(|i| start + i + 1).unwrap_or(out.len());
+    out.replace_range(start..end, &base_tag);
+  } else if let Some(start) = lower.find("<head>").or_else(|| lower.find("<head ")) {
+    let end = lower[start..].find('>').map(|i| start + i + 1).unwrap_or(out.len());
+    out.insert_str(end, &base_tag);
   }
   out
 }
@@ -968,6 +7492,8 @@ fn sanitize_request_headers(mut headers: HeaderMap) -> HeaderMap {
   headers.remove(header::COOKIE);
   headers.remove(header::AUTHORIZATION);
   headers.remove(header::HOST);
+  headers.remove(HEADER_SERVER_OVERRIDE);
+  headers.remove(HEADER_UPSTREAM_TIMEOUT);
   headers
 }
 
@@ -1007,6 +7533,39 @@ fn remove_hop_headers(headers: &mut HeaderMap) {
   }
 }
 
+/// An upstream body read by [`inspect_body`].
+enum InspectedBody {
+  /// The whole body, within the limit.
+  Complete(Bytes),
+  /// Over the limit: the bytes read so far followed by the rest of the
+  /// upstream stream, to be passed on untouched.
+  Streaming(Body),
+}
+
+/// Buffers an upstream body for features that need to look at it, up to
+/// `limit` bytes. A body declared or found to be larger is handed back as a
+/// stream instead, so at most `limit` bytes of a large download are ever
+/// held in memory.
+async fn inspect_body(resp: reqwest::Response, limit: usize) -> reqwest::Result<InspectedBody> {
+  if resp.content_length().is_some_and(|n| n > limit as u64) {
+    let stream = resp.bytes_stream().map_err(std::io::Error::other);
+    return Ok(InspectedBody::Streaming(Body::from_stream(stream)));
+  }
+
+  let mut buf = BytesMut::new();
+  let mut stream = resp.bytes_stream();
+  while let Some(next) = stream.next().await {
+    let chunk = next?;
+    if buf.len().saturating_add(chunk.len()) > limit {
+      let head = futures_util::stream::iter([Ok(buf.freeze()), Ok(chunk)]);
+      let stream = head.chain(stream).map_err(std::io::Error::other);
+      return Ok(InspectedBody::Streaming(Body::from_stream(stream)));
+    }
+    buf.extend_from_slice(&chunk);
+  }
+  Ok(InspectedBody::Complete(buf.freeze()))
+}
+
 #[derive(Debug)]
 enum ReadBodyError {
   TooLarge,
@@ -1032,3 +7591,131 @@ async fn read_body_bytes(body: Body, limit: usize) -> std::result::Result<Vec<u8
 
   Ok(out)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn target(base: &str, path_and_query: &str) -> String {
+    let base = normalize_base_url(Url::parse(base).unwrap());
+    let uri = path_and_query.parse::<Uri>().unwrap();
+    build_target_url(&base, &uri).unwrap().to_string()
+  }
+
+  #[test]
+  fn target_url_joins_base_without_subpath() {
+    for base in ["http://qb:8080", "http://qb:8080/"] {
+      assert_eq!(target(base, "/api/v2/torrents/info"), "http://qb:8080/api/v2/torrents/info");
+    }
+  }
+
+  #[test]
+  fn target_url_joins_base_with_subpath() {
+    for base in ["http://nas/qbittorrent", "http://nas/qbittorrent/"] {
+      assert_eq!(
+        target(base, "/api/v2/torrents/info"),
+        "http://nas/qbittorrent/api/v2/torrents/info"
+      );
+    }
+  }
+
+  #[test]
+  fn target_url_keeps_query_and_drops_base_query() {
+    assert_eq!(
+      target("http://nas/qb/?x=1#top", "/api/v2/torrents/info?filter=all"),
+      "http://nas/qb/api/v2/torrents/info?filter=all"
+    );
+  }
+
+  fn client(last: u8) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+  }
+
+  async fn wait_for_depth(queue: &FairQueue, depth: usize) {
+    while queue.depth() < depth {
+      tokio::task::yield_now().await;
+    }
+  }
+
+  #[tokio::test]
+  async fn fair_queue_hands_permits_round_robin() {
+    let queue = Arc::new(FairQueue::new(1));
+    let held = queue.acquire(client(1)).await;
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    for (n, (ip, label)) in [(client(1), "a1"), (client(1), "a2"), (client(2), "b1")]
+      .into_iter()
+      .enumerate()
+    {
+      let shared = queue.clone();
+      let tx = tx.clone();
+      tokio::spawn(async move {
+        let permit = shared.acquire(ip).await;
+        tx.send((label, permit)).unwrap();
+      });
+      wait_for_depth(&queue, n + 1).await;
+    }
+
+    drop(held);
+    let mut order = Vec::new();
+    for _ in 0..3 {
+      let (label, permit) = rx.recv().await.unwrap();
+      assert_eq!(queue.state.lock().unwrap().active, 1);
+      order.push(label);
+      drop(permit);
+    }
+    assert_eq!(order, ["a1", "b1", "a2"]);
+    assert_eq!(queue.state.lock().unwrap().active, 0);
+    assert_eq!(queue.depth(), 0);
+  }
+
+  #[tokio::test]
+  async fn fair_queue_skips_abandoned_waiters() {
+    let queue = Arc::new(FairQueue::new(1));
+    let held = queue.acquire(client(1)).await;
+    let waiter = tokio::spawn({
+      let queue = queue.clone();
+      async move { queue.acquire(client(2)).await }
+    });
+    wait_for_depth(&queue, 1).await;
+    waiter.abort();
+    let _ = waiter.await;
+
+    drop(held);
+    assert_eq!(queue.state.lock().unwrap().active, 0);
+    let _permit = queue.acquire(client(3)).await;
+    assert_eq!(queue.state.lock().unwrap().active, 1);
+  }
+
+  #[test]
+  fn normalize_path_resolves_alternate_spellings() {
+    assert_eq!(normalize_path("/api/v2/app/shutdown"), "/api/v2/app/shutdown");
+    assert_eq!(normalize_path("/api/v2/app/%73hutdown"), "/api/v2/app/shutdown");
+    assert_eq!(normalize_path("/api//v2///app/shutdown"), "/api/v2/app/shutdown");
+    assert_eq!(normalize_path("/api/v2/torrents/../app/./shutdown"), "/api/v2/app/shutdown");
+    assert_eq!(normalize_path("/api/v2/%2E%2E/v2/app/shutdown"), "/api/v2/app/shutdown");
+    assert_eq!(normalize_path("/api/v2/"), "/api/v2/");
+    assert_eq!(normalize_path("/../.."), "/");
+    // Reserved characters stay encoded.
+    assert_eq!(normalize_path("/a%2Fb%3f"), "/a%2Fb%3f");
+    assert_eq!(normalize_path("/a%7"), "/a%7");
+  }
+
+  #[test]
+  fn retry_after_stays_within_jitter() {
+    let retry = RetryAfter::new(5, 3);
+    for _ in 0..64 {
+      assert!((5..=8).contains(&retry.next_secs()));
+    }
+    let retry = RetryAfter::new(u64::MAX, u64::MAX);
+    assert_eq!(retry.next_secs(), u64::MAX);
+  }
+
+  #[test]
+  fn join_path_handles_slashes() {
+    assert_eq!(join_path("", "/api"), "/api");
+    assert_eq!(join_path("/qb", "/api"), "/qb/api");
+    assert_eq!(join_path("/qb/", "/api"), "/qb/api");
+    assert_eq!(join_path("/qb", "api"), "/qb/api");
+    assert_eq!(join_path("/qb/", "api"), "/qb/api");
+  }
+}
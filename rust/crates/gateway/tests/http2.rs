@@ -0,0 +1,70 @@
+//! Requests to servers configured with `http2` reach the backend over HTTP/2.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode, Version};
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use gateway::{spawn_with_config, GatewayHandle};
+
+fn fixture(name: &str) -> PathBuf {
+  PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+/// Answers every request with the HTTP version it arrived with.
+fn version_app() -> Router {
+  Router::new().fallback(|req: Request<Body>| async move { format!("{:?}", req.version()) })
+}
+
+async fn gateway(server: serde_json::Value) -> GatewayHandle {
+  let config = serde_json::json!({ "defaultServerId": "s", "servers": [server] });
+  spawn_with_config(&config.to_string(), "/nonexistent".into()).await.unwrap()
+}
+
+async fn upstream_version(gw: &GatewayHandle) -> String {
+  let resp = reqwest::get(format!("http://{}/transmission/web/", gw.addr())).await.unwrap();
+  assert_eq!(resp.status(), StatusCode::OK);
+  resp.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn prior_knowledge_speaks_h2c() {
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let addr: SocketAddr = listener.local_addr().unwrap();
+  let server = axum_server::from_tcp(listener);
+  tokio::spawn(async move { server.serve(version_app().into_make_service()).await.unwrap() });
+
+  let gw = gateway(serde_json::json!({
+    "id": "s",
+    "type": "trans",
+    "baseUrl": format!("http://{addr}"),
+    "http2": "prior-knowledge",
+  }))
+  .await;
+  assert_eq!(upstream_version(&gw).await, format!("{:?}", Version::HTTP_2));
+
+  gw.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn alpn_negotiates_h2_over_tls() {
+  let tls = RustlsConfig::from_pem_file(fixture("leaf.pem"), fixture("leaf.key")).await.unwrap();
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let addr: SocketAddr = listener.local_addr().unwrap();
+  let server = axum_server::from_tcp_rustls(listener, tls);
+  tokio::spawn(async move { server.serve(version_app().into_make_service()).await.unwrap() });
+
+  let gw = gateway(serde_json::json!({
+    "id": "s",
+    "type": "trans",
+    "baseUrl": format!("https://{addr}"),
+    "caCertPath": fixture("ca.pem"),
+    "http2": "alpn",
+  }))
+  .await;
+  assert_eq!(upstream_version(&gw).await, format!("{:?}", Version::HTTP_2));
+
+  gw.shutdown().await.unwrap();
+}